@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toshi_types::error::Error;
+use toshi_types::server::{Alias, AliasAction};
+
+use crate::utils::{glob_match, write_atomically};
+use crate::Result;
+
+/// Name of the file aliases are kept in under the catalog's base path.
+const ALIASES_FILE: &str = ".aliases.json";
+
+/// Alternative names that resolve to one or more concrete indexes.
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    /// Where the aliases are persisted, aliases of a catalog without a base path only live in memory.
+    path: Option<PathBuf>,
+    aliases: BTreeMap<String, Alias>,
+}
+
+impl Aliases {
+    /// Loads the aliases persisted under `base_path`, if there are any.
+    pub fn load(base_path: &Path) -> Result<Self> {
+        let path = base_path.join(ALIASES_FILE);
+        let aliases = if path.exists() {
+            serde_json::from_slice(&fs::read(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { path: Some(path), aliases })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Alias> {
+        self.aliases.get(name)
+    }
+
+    pub fn all(&self) -> &BTreeMap<String, Alias> {
+        &self.aliases
+    }
+
+    /// The indexes of every alias whose name matches `pattern`.
+    pub fn matching(&self, pattern: &str) -> impl Iterator<Item = &String> {
+        let pattern = pattern.to_string();
+        self.aliases
+            .iter()
+            .filter(move |(name, _)| glob_match(&pattern, name))
+            .flat_map(|(_, alias)| alias.indexes.iter())
+    }
+
+    /// Applies every action to a copy of the aliases and only keeps the result if all of them
+    /// succeed and every alias is left with a write index, so readers never see half a swap.
+    pub fn apply<F: Fn(&str) -> bool>(&mut self, actions: &[AliasAction], is_index: F) -> Result<()> {
+        let mut aliases = self.aliases.clone();
+        for action in actions {
+            match action {
+                AliasAction::Add {
+                    index,
+                    alias,
+                    is_write_index,
+                } => {
+                    if !is_index(index) {
+                        return Err(Error::UnknownIndex(index.clone()));
+                    }
+                    if is_index(alias) {
+                        return Err(Error::QueryError(format!("alias '{}' has the same name as an index", alias)));
+                    }
+                    let entry = aliases.entry(alias.clone()).or_default();
+                    entry.indexes.insert(index.clone());
+                    if *is_write_index {
+                        entry.write_index = Some(index.clone());
+                    }
+                }
+                AliasAction::Remove { index, alias } => {
+                    let not_in_alias = || Error::QueryError(format!("index '{}' is not in alias '{}'", index, alias));
+                    let entry = aliases.get_mut(alias).ok_or_else(not_in_alias)?;
+                    if !entry.indexes.remove(index) {
+                        return Err(not_in_alias());
+                    }
+                    if entry.write_index.as_ref() == Some(index) {
+                        entry.write_index = None;
+                    }
+                }
+            }
+        }
+
+        aliases.retain(|_, alias| !alias.indexes.is_empty());
+        for (name, alias) in aliases.iter_mut() {
+            if alias.indexes.len() == 1 {
+                alias.write_index = alias.indexes.iter().next().cloned();
+            } else if alias.write_index.is_none() {
+                return Err(Error::QueryError(format!(
+                    "alias '{}' points to several indexes, one has to be added with is_write_index",
+                    name
+                )));
+            }
+        }
+        self.save(&aliases)?;
+        self.aliases = aliases;
+        Ok(())
+    }
+
+    /// Takes a dropped index out of every alias. An alias left with one index writes to it, one left
+    /// with several but without its write index rejects writes until a new one is set.
+    pub fn remove_index(&mut self, index: &str) -> Result<()> {
+        let mut aliases = self.aliases.clone();
+        for alias in aliases.values_mut() {
+            alias.indexes.remove(index);
+            if alias.write_index.as_deref() == Some(index) {
+                alias.write_index = None;
+            }
+            if alias.indexes.len() == 1 {
+                alias.write_index = alias.indexes.iter().next().cloned();
+            }
+        }
+        aliases.retain(|_, alias| !alias.indexes.is_empty());
+        if aliases != self.aliases {
+            self.save(&aliases)?;
+            self.aliases = aliases;
+        }
+        Ok(())
+    }
+
+    fn save(&self, aliases: &BTreeMap<String, Alias>) -> Result<()> {
+        if let Some(path) = &self.path {
+            write_atomically(path, &serde_json::to_vec(aliases)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn add(index: &str, alias: &str, is_write_index: bool) -> AliasAction {
+        AliasAction::Add {
+            index: index.into(),
+            alias: alias.into(),
+            is_write_index,
+        }
+    }
+
+    fn remove(index: &str, alias: &str) -> AliasAction {
+        AliasAction::Remove {
+            index: index.into(),
+            alias: alias.into(),
+        }
+    }
+
+    #[test]
+    fn test_alias_swap() {
+        let is_index = |name: &str| name.starts_with("products-");
+        let mut aliases = Aliases::default();
+        aliases.apply(&[add("products-1", "products", false)], is_index).unwrap();
+        assert_eq!(aliases.get("products").unwrap().write_index.as_deref(), Some("products-1"));
+
+        aliases
+            .apply(&[add("products-2", "products", false), remove("products-1", "products")], is_index)
+            .unwrap();
+        let products = aliases.get("products").unwrap();
+        assert_eq!(products.indexes.iter().collect::<Vec<_>>(), vec!["products-2"]);
+        assert_eq!(products.write_index.as_deref(), Some("products-2"));
+
+        aliases
+            .apply(&[add("products-3", "all", false), add("products-2", "all", true)], is_index)
+            .unwrap();
+        assert_eq!(aliases.matching("a*").count(), 2);
+    }
+
+    #[test]
+    fn test_failed_actions_change_nothing() {
+        let is_index = |name: &str| name.starts_with("products-");
+        let mut aliases = Aliases::default();
+        aliases.apply(&[add("products-1", "products", false)], is_index).unwrap();
+        let before = aliases.all().clone();
+
+        let no_write_index = [add("products-1", "both", false), add("products-2", "both", false)];
+        assert!(aliases.apply(&no_write_index, is_index).is_err());
+        let missing = [remove("products-1", "products"), add("nope", "products", false)];
+        assert!(aliases.apply(&missing, is_index).is_err());
+        assert!(aliases.apply(&[add("products-1", "products-2", false)], is_index).is_err());
+        assert!(aliases.apply(&[remove("products-2", "products")], is_index).is_err());
+        assert_eq!(aliases.all(), &before);
+
+        aliases.apply(&[remove("products-1", "products")], is_index).unwrap();
+        assert!(aliases.get("products").is_none());
+    }
+}
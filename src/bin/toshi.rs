@@ -16,6 +16,7 @@ use toshi_server::cluster::rpc_server::RpcServer;
 use toshi_server::commit::watcher;
 use toshi_server::index::{IndexCatalog, SharedCatalog};
 use toshi_server::router::router_with_catalog;
+use toshi_server::scroll::{reaper, REAP_INTERVAL};
 use toshi_server::settings::{Settings, HEADER, RPC_HEADER};
 use toshi_server::{shutdown, support};
 
@@ -65,8 +66,12 @@ fn setup_toshi(settings: &Settings, index_catalog: &SharedCatalog, tx: Sender<()
     } else {
         future::Either::B(run_master(Arc::clone(index_catalog), &settings))
     };
+    let scroll_reaper = reaper(Arc::clone(index_catalog), REAP_INTERVAL);
     let shutdown = shutdown::shutdown(tx);
-    server.select(shutdown).map(|_| ()).map_err(|_| ())
+    future::lazy(move || {
+        tokio::spawn(scroll_reaper);
+        server.select(shutdown).map(|_| ()).map_err(|_| ())
+    })
 }
 
 #[cfg_attr(tarpaulin, skip)]
@@ -5,9 +5,11 @@ use tokio::prelude::*;
 use tower_grpc::{Request as TowerRequest, Response, Status};
 use tracing::*;
 
-use toshi_proto::cluster_rpc::{DeleteRequest, DocumentRequest, SearchReply, SearchRequest};
+use toshi_proto::cluster_rpc::{
+    CountReply, DeleteRequest, DocumentRequest, DropRequest, ResultReply, SearchReply, SearchRequest, SummaryReply, SummaryRequest,
+};
 use toshi_types::query::Search;
-use toshi_types::server::DeleteDoc;
+use toshi_types::server::{DeleteByQuery, DeleteDoc};
 
 use crate::cluster::rpc_server::RpcClient;
 use crate::handle::{IndexHandle, IndexLocation};
@@ -45,6 +47,76 @@ impl RemoteIndex {
     pub fn with_clients(name: String, remotes: Vec<RpcClient>) -> Self {
         Self { name, remotes }
     }
+
+    pub fn summary(&self) -> Box<dyn Future<Item = Vec<SummaryReply>, Error = Status> + Send> {
+        let name = self.name.clone();
+        let clients = self.remotes.clone();
+        let fut = clients.into_iter().map(move |mut client| {
+            let req = TowerRequest::new(SummaryRequest { index: name.clone() });
+            client.get_summary(req).map(Response::into_inner).map_err(|e| {
+                info!("ERR = {:?}", e);
+                e
+            })
+        });
+
+        Box::new(future::join_all(fut))
+    }
+
+    pub fn count(&self, search: Search) -> Box<dyn Future<Item = Vec<CountReply>, Error = Status> + Send> {
+        let name = self.name.clone();
+        let clients = self.remotes.clone();
+        let fut = clients.into_iter().map(move |mut client| {
+            let bytes = serde_json::to_vec(&search).unwrap_or_default();
+            let req = TowerRequest::new(SearchRequest {
+                index: name.clone(),
+                query: bytes,
+            });
+            client.count_index(req).map(Response::into_inner).map_err(|e| {
+                info!("ERR = {:?}", e);
+                e
+            })
+        });
+
+        Box::new(future::join_all(fut))
+    }
+
+    pub fn delete_by_query(&self, delete: DeleteByQuery) -> Box<dyn Future<Item = Vec<ResultReply>, Error = Status> + Send> {
+        let name = self.name.clone();
+        let clients = self.remotes.clone();
+        let fut = clients.into_iter().map(move |mut client| {
+            let bytes = serde_json::to_vec(&delete).unwrap_or_default();
+            let req = TowerRequest::new(DeleteRequest {
+                index: name.clone(),
+                terms: bytes,
+            });
+            client.delete_document(req).map(Response::into_inner).map_err(|e| {
+                info!("ERR = {:?}", e);
+                e
+            })
+        });
+
+        Box::new(future::join_all(fut))
+    }
+
+    pub fn drop_index(&self) -> Box<dyn Future<Item = Vec<i32>, Error = Status> + Send> {
+        let name = self.name.clone();
+        let clients = self.remotes.clone();
+        let fut = clients.into_iter().map(move |mut client| {
+            let req = TowerRequest::new(DropRequest { index: name.clone() });
+            client
+                .drop_index(req)
+                .map(|res| {
+                    info!("RESPONSE = {:?}", res);
+                    res.into_inner().code
+                })
+                .map_err(|e| {
+                    info!("ERR = {:?}", e);
+                    e
+                })
+        });
+
+        Box::new(future::join_all(fut))
+    }
 }
 
 impl IndexHandle for RemoteIndex {
@@ -18,12 +18,12 @@ use tower_request_modifier::{Builder, RequestModifier};
 use tracing::*;
 
 use toshi_proto::cluster_rpc::*;
-use toshi_types::query::Search;
+use toshi_types::query::{Query, Search};
 
-use crate::handle::IndexHandle;
+use crate::handle::{IndexHandle, LocalIndex};
 use crate::index::IndexCatalog;
 use crate::AddDocument;
-use toshi_types::server::DeleteDoc;
+use toshi_types::server::{DeleteByQuery, DeleteDoc, IndexSettings};
 
 pub type Buf = Buffer<RequestModifier<Connection<BoxBody>, BoxBody>, http::Request<BoxBody>>;
 pub type RpcClient = client::IndexService<Buf>;
@@ -104,6 +104,8 @@ impl server::IndexService for RpcServer {
     type DeleteDocumentFuture = Box<future::FutureResult<Response<ResultReply>, Status>>;
     type GetSummaryFuture = Box<future::FutureResult<Response<SummaryReply>, Status>>;
     type BulkInsertFuture = Box<future::FutureResult<Response<ResultReply>, Status>>;
+    type DropIndexFuture = Box<future::FutureResult<Response<ResultReply>, Status>>;
+    type CountIndexFuture = Box<future::FutureResult<Response<CountReply>, Status>>;
 
     fn list_indexes(&mut self, req: Request<ListRequest>) -> Self::ListIndexesFuture {
         let cat = self.catalog.read();
@@ -140,11 +142,22 @@ impl server::IndexService for RpcServer {
     }
 
     fn place_index(&mut self, request: Request<PlaceRequest>) -> Self::PlaceIndexFuture {
-        let PlaceRequest { index, schema } = request.into_inner();
+        let PlaceRequest { index, schema, settings } = request.into_inner();
         let mut cat = self.catalog.write();
+        let settings: IndexSettings = if settings.is_empty() {
+            IndexSettings::default()
+        } else {
+            match serde_json::from_slice(&settings) {
+                Ok(v) => v,
+                Err(_) => return Self::error_response(Code::InvalidArgument, "Invalid settings in request".into()),
+            }
+        };
         if let Ok(schema) = serde_json::from_slice::<Schema>(&schema) {
             let ip = cat.base_path().clone();
             if let Ok(new_index) = IndexCatalog::create_from_managed(ip, &index.clone(), schema) {
+                if let Err(e) = LocalIndex::write_settings(&new_index, &settings) {
+                    return Self::error_response(Code::InvalidArgument, e.to_string());
+                }
                 if cat.add_index(index.clone(), new_index).is_ok() {
                     Box::new(future::finished(Response::new(RpcServer::ok_result())))
                 } else {
@@ -182,9 +195,18 @@ impl server::IndexService for RpcServer {
 
     fn delete_document(&mut self, request: Request<DeleteRequest>) -> Self::DeleteDocumentFuture {
         let DeleteRequest { index, terms } = request.into_inner();
-        let cat = self.catalog.read();
-        if let Ok(idx) = cat.get_index(&index) {
-            if let Ok(delete_docs) = serde_json::from_slice::<DeleteDoc>(&terms) {
+        let handle = self.catalog.read().get_owned_index(&index);
+        if let Ok(idx) = handle {
+            // A delete by query replies with how many documents it removed, which the caller adds to its own count.
+            if let Ok(delete) = serde_json::from_slice::<DeleteByQuery>(&terms) {
+                match idx.delete_by_query(delete.query) {
+                    Ok(affected) => {
+                        let message = serde_json::to_string(&affected).unwrap_or_default();
+                        Box::new(future::finished(Response::new(RpcServer::create_result(0, message))))
+                    }
+                    Err(e) => Self::error_response(Code::Internal, format!("Delete by query failed on {}: {}", index, e)),
+                }
+            } else if let Ok(delete_docs) = serde_json::from_slice::<DeleteDoc>(&terms) {
                 if idx.delete_term(delete_docs).is_ok() {
                     Box::new(future::finished(Response::new(RpcServer::ok_result())))
                 } else {
@@ -216,6 +238,53 @@ impl server::IndexService for RpcServer {
         unimplemented!()
     }
 
+    fn drop_index(&mut self, request: Request<DropRequest>) -> Self::DropIndexFuture {
+        let DropRequest { index } = request.into_inner();
+        let mut cat = self.catalog.write();
+        if cat.aliases().get(&index).is_some() {
+            return Self::error_response(
+                Code::InvalidArgument,
+                format!("'{}' is an alias, drop the indexes it points to instead", index),
+            );
+        }
+        if !cat.exists(&index) {
+            return Self::error_response(Code::NotFound, "Could not find index".into());
+        }
+        match cat.remove_index(&index) {
+            Ok(_) => Box::new(future::finished(Response::new(RpcServer::ok_result()))),
+            Err(e @ toshi_types::error::Error::QueryError(_)) => {
+                Self::error_response(Code::FailedPrecondition, format!("Drop Index Failed: {}, {}", index, e))
+            }
+            Err(e) => Self::error_response(Code::Internal, format!("Drop Index Failed: {}, {}", index, e)),
+        }
+    }
+
+    fn count_index(&mut self, request: Request<SearchRequest>) -> Self::CountIndexFuture {
+        let inner = request.into_inner();
+        let cat = self.catalog.read();
+        if let Ok(index) = cat.get_index(&inner.index) {
+            let query: Search = match serde_json::from_slice(&inner.query) {
+                Ok(v) => v,
+                Err(e) => return Self::error_response(Code::InvalidArgument, e.to_string()),
+            };
+            match index.count(query.query.unwrap_or(Query::All)) {
+                Ok(count) => {
+                    let reply = CountReply {
+                        result: Some(RpcServer::ok_result()),
+                        count: count as u64,
+                    };
+                    Box::new(future::finished(Response::new(reply)))
+                }
+                Err(e @ toshi_types::error::Error::QueryError(_)) | Err(e @ toshi_types::error::Error::UnknownIndexField(_)) => {
+                    Self::error_response(Code::InvalidArgument, e.to_string())
+                }
+                Err(e) => Self::error_response(Code::Internal, e.to_string()),
+            }
+        } else {
+            Self::error_response(Code::NotFound, format!("Index: {} not found", inner.index))
+        }
+    }
+
     fn ping(&mut self, _: Request<PingRequest>) -> Self::PingFuture {
         Box::new(future::ok(Response::new(PingReply { status: "OK".into() })))
     }
@@ -0,0 +1,585 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use tantivy::chrono::NaiveDateTime;
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::fastfield::{FastFieldReader, MultiValueIntFastFieldReader};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Value};
+use tantivy::store::StoreReader;
+use tantivy::{DocId, DocSet, InvertedIndexReader, Score, SegmentLocalId, SegmentReader, TantivyError};
+
+use toshi_types::aggregation::{AggregationResult, Bucket, BucketKey, BucketsResult, MetricsResult, TermsBucket, TermsResult};
+use toshi_types::query::{AggregationRange, CalendarInterval, SortValue};
+
+/// Computes terms, metric and bucket aggregations over every matching document. Numeric values are
+/// read from u64, i64 or f64 fast fields, single or multi valued. Text fields can't be fast fields in
+/// tantivy, so terms aggregations over them walk the postings of every term in the segment instead,
+/// on every search. That's only affordable for raw tokenized fields, whose terms are whole values such
+/// as tags or ids, which is what searches are limited to.
+/// Dates can't be fast fields either, so bucket aggregations over a date field read it from the doc
+/// store, which is much slower than indexing the timestamp in seconds as an i64 fast field.
+///
+/// Each segment counts every value it sees, terms buckets are only cut down to their `shard_size`
+/// once all segments are merged.
+#[derive(Default)]
+pub struct Aggregations {
+    aggs: Vec<Spec>,
+}
+
+/// How a bucket aggregation sorts values into buckets.
+#[derive(Clone)]
+pub enum Buckets {
+    Histogram(f64),
+    DateHistogram(CalendarInterval),
+    Range(Vec<AggregationRange>),
+}
+
+impl Buckets {
+    /// Adds the key of every bucket `value` belongs in to `keys`, for ranges the key is the range's position.
+    fn keys(&self, value: SortValue, keys: &mut Vec<i64>) {
+        match self {
+            Buckets::Histogram(interval) => keys.push((value.as_f64() / interval).floor() as i64),
+            Buckets::DateHistogram(interval) => {
+                let timestamp = match value {
+                    SortValue::I64(v) => v,
+                    SortValue::U64(v) => v as i64,
+                    SortValue::F64(v) => v as i64,
+                };
+                keys.push(interval.floor(timestamp));
+            }
+            Buckets::Range(ranges) => {
+                let value = value.as_f64();
+                keys.extend(ranges.iter().enumerate().filter(|(_, r)| r.contains(value)).map(|(i, _)| i as i64));
+            }
+        }
+    }
+
+    fn bucket(&self, key: i64, doc_count: u64) -> Bucket {
+        match self {
+            Buckets::Histogram(interval) => Bucket::new(BucketKey::Number(SortValue::F64(key as f64 * interval)), doc_count),
+            Buckets::DateHistogram(_) => {
+                let date = NaiveDateTime::from_timestamp(key, 0).format("%Y-%m-%dT%H:%M:%SZ");
+                Bucket::new(BucketKey::Number(SortValue::I64(key)), doc_count).with_key_as_string(date.to_string())
+            }
+            Buckets::Range(ranges) => {
+                let range = &ranges[key as usize];
+                Bucket::new(BucketKey::Text(range.key()), doc_count).with_bounds(range.from, range.to)
+            }
+        }
+    }
+
+    fn result(&self, buckets: BucketsResult) -> AggregationResult {
+        match self {
+            Buckets::Histogram(_) => AggregationResult::Histogram(buckets),
+            Buckets::DateHistogram(_) => AggregationResult::DateHistogram(buckets),
+            Buckets::Range(_) => AggregationResult::Range(buckets),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Kind {
+    Terms(usize),
+    Metrics,
+    Buckets(Buckets),
+}
+
+struct Spec {
+    name: String,
+    field: Field,
+    kind: Kind,
+    metrics: Vec<(String, Field)>,
+}
+
+impl Aggregations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(mut self, name: String, field: Field, kind: Kind, metrics: Vec<(String, Field)>) -> Self {
+        self.aggs.push(Spec {
+            name,
+            field,
+            kind,
+            metrics,
+        });
+        self
+    }
+
+    /// The field must be a u64, i64 or f64 fast field or an indexed text field.
+    pub fn terms<N: ToString>(self, name: N, field: Field, shard_size: usize) -> Self {
+        self.push(name.to_string(), field, Kind::Terms(shard_size), Vec::new())
+    }
+
+    /// The field must be a u64, i64 or f64 fast field.
+    pub fn metrics<N: ToString>(self, name: N, field: Field) -> Self {
+        self.push(name.to_string(), field, Kind::Metrics, Vec::new())
+    }
+
+    /// The field must be a u64, i64 or f64 fast field or a stored date field, `metrics` are computed
+    /// within each bucket and must be over u64, i64 or f64 fast fields.
+    pub fn buckets<N: ToString>(self, name: N, field: Field, buckets: Buckets, metrics: Vec<(String, Field)>) -> Self {
+        self.push(name.to_string(), field, Kind::Buckets(buckets), metrics)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aggs.is_empty()
+    }
+
+    fn empty_result(kind: &Kind) -> AggregationResult {
+        match kind {
+            Kind::Terms(_) => AggregationResult::Terms(TermsResult::default()),
+            Kind::Metrics => AggregationResult::Metrics(MetricsResult::default()),
+            Kind::Buckets(buckets) => buckets.result(BucketsResult::default()),
+        }
+    }
+}
+
+impl Collector for Aggregations {
+    type Fruit = BTreeMap<String, AggregationResult>;
+    type Child = AggregationsSegment;
+
+    fn for_segment(&self, _: SegmentLocalId, reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        let open = |name: &str, field: Field| {
+            ValueReader::open(reader, field)
+                .ok_or_else(|| TantivyError::SchemaError(format!("Field {:?} can't be used in aggregation {}", field, name)))
+        };
+        let aggs = self
+            .aggs
+            .iter()
+            .map(|spec| {
+                let field_type = reader.schema().get_field_entry(spec.field).field_type();
+                let source = match (&spec.kind, field_type) {
+                    (Kind::Terms(_), FieldType::Str(opts)) if opts.get_indexing_options().is_some() => {
+                        Source::Terms(reader.inverted_index(spec.field))
+                    }
+                    _ => Source::Values(open(&spec.name, spec.field)?),
+                };
+                let state = match &spec.kind {
+                    Kind::Terms(_) => State::Terms(HashMap::new()),
+                    Kind::Metrics => State::Metrics(MetricsResult::default()),
+                    Kind::Buckets(buckets) => {
+                        let metrics = spec
+                            .metrics
+                            .iter()
+                            .map(|(name, field)| Ok((name.clone(), open(name, *field)?)))
+                            .collect::<tantivy::Result<Vec<_>>>()?;
+                        let mut counts = BTreeMap::new();
+                        if let Buckets::Range(ranges) = buckets {
+                            for key in 0..ranges.len() {
+                                counts.insert(key as i64, BucketState::new(metrics.len()));
+                            }
+                        }
+                        State::Buckets {
+                            buckets: buckets.clone(),
+                            metrics,
+                            counts,
+                        }
+                    }
+                };
+                Ok(SegmentAggregation {
+                    name: spec.name.clone(),
+                    source,
+                    state,
+                })
+            })
+            .collect::<tantivy::Result<Vec<_>>>()?;
+
+        Ok(AggregationsSegment {
+            aggs,
+            docs: Vec::new(),
+            scratch: Scratch::default(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(&self, segment_fruits: Vec<Self::Fruit>) -> tantivy::Result<Self::Fruit> {
+        let mut merged: Self::Fruit = self
+            .aggs
+            .iter()
+            .map(|spec| (spec.name.clone(), Aggregations::empty_result(&spec.kind)))
+            .collect();
+        for fruit in segment_fruits {
+            toshi_types::aggregation::merge_aggregations(&mut merged, fruit);
+        }
+        for spec in &self.aggs {
+            if let (Kind::Terms(shard_size), Some(AggregationResult::Terms(terms))) = (&spec.kind, merged.get_mut(&spec.name)) {
+                terms.truncate(*shard_size);
+            }
+        }
+        Ok(merged)
+    }
+}
+
+enum ValueReader {
+    U64(FastFieldReader<u64>),
+    I64(FastFieldReader<i64>),
+    F64(FastFieldReader<f64>),
+    U64s(MultiValueIntFastFieldReader<u64>),
+    I64s(MultiValueIntFastFieldReader<i64>),
+    F64s(MultiValueIntFastFieldReader<f64>),
+    Dates(StoreReader, Field),
+}
+
+impl ValueReader {
+    fn open(reader: &SegmentReader, field: Field) -> Option<Self> {
+        let fast_fields = reader.fast_fields();
+        fast_fields
+            .u64(field)
+            .map(ValueReader::U64)
+            .or_else(|| fast_fields.i64(field).map(ValueReader::I64))
+            .or_else(|| fast_fields.f64(field).map(ValueReader::F64))
+            .or_else(|| fast_fields.u64s(field).map(ValueReader::U64s))
+            .or_else(|| fast_fields.i64s(field).map(ValueReader::I64s))
+            .or_else(|| fast_fields.f64s(field).map(ValueReader::F64s))
+            .or_else(|| {
+                let entry = reader.schema().get_field_entry(field);
+                match entry.field_type() {
+                    FieldType::Date(_) if entry.is_stored() => Some(ValueReader::Dates(reader.get_store_reader(), field)),
+                    _ => None,
+                }
+            })
+    }
+
+    /// Replaces `out` with the doc's values in their fast field encoding, which can be hashed and
+    /// is turned back into a value with `decode`. Dates are read as timestamps in seconds.
+    fn read(&self, doc: DocId, out: &mut Vec<u64>) {
+        out.clear();
+        match self {
+            ValueReader::U64(r) => out.push(r.get(doc)),
+            ValueReader::I64(r) => out.push(tantivy::i64_to_u64(r.get(doc))),
+            ValueReader::F64(r) => out.push(tantivy::f64_to_u64(r.get(doc))),
+            ValueReader::U64s(r) => r.get_vals(doc, out),
+            ValueReader::I64s(r) => {
+                let mut vals = Vec::new();
+                r.get_vals(doc, &mut vals);
+                out.extend(vals.into_iter().map(tantivy::i64_to_u64));
+            }
+            ValueReader::F64s(r) => {
+                let mut vals = Vec::new();
+                r.get_vals(doc, &mut vals);
+                out.extend(vals.into_iter().map(tantivy::f64_to_u64));
+            }
+            ValueReader::Dates(store, field) => {
+                if let Ok(stored) = store.get(doc) {
+                    out.extend(stored.get_all(*field).into_iter().filter_map(|v| match v {
+                        Value::Date(d) => Some(tantivy::i64_to_u64(d.timestamp())),
+                        _ => None,
+                    }));
+                }
+            }
+        }
+    }
+
+    fn decode(&self, raw: u64) -> SortValue {
+        match self {
+            ValueReader::U64(_) | ValueReader::U64s(_) => SortValue::U64(raw),
+            ValueReader::I64(_) | ValueReader::I64s(_) | ValueReader::Dates(..) => SortValue::I64(tantivy::u64_to_i64(raw)),
+            ValueReader::F64(_) | ValueReader::F64s(_) => SortValue::F64(tantivy::u64_to_f64(raw)),
+        }
+    }
+}
+
+enum Source {
+    Values(ValueReader),
+    Terms(Arc<InvertedIndexReader>),
+}
+
+struct BucketState {
+    doc_count: u64,
+    metrics: Vec<MetricsResult>,
+}
+
+impl BucketState {
+    fn new(metrics: usize) -> Self {
+        Self {
+            doc_count: 0,
+            metrics: vec![MetricsResult::default(); metrics],
+        }
+    }
+}
+
+enum State {
+    Terms(HashMap<u64, u64>),
+    Metrics(MetricsResult),
+    Buckets {
+        buckets: Buckets,
+        metrics: Vec<(String, ValueReader)>,
+        counts: BTreeMap<i64, BucketState>,
+    },
+}
+
+/// Buffers reused for every document so collecting doesn't allocate.
+#[derive(Default)]
+struct Scratch {
+    values: Vec<u64>,
+    keys: Vec<i64>,
+    metrics: Vec<Vec<u64>>,
+}
+
+struct SegmentAggregation {
+    name: String,
+    source: Source,
+    state: State,
+}
+
+impl SegmentAggregation {
+    fn collect(&mut self, doc: DocId, scratch: &mut Scratch) {
+        let reader = match &self.source {
+            Source::Values(reader) => reader,
+            Source::Terms(_) => return,
+        };
+        let values = &mut scratch.values;
+        reader.read(doc, values);
+        match &mut self.state {
+            State::Terms(counts) => {
+                values.sort_unstable();
+                values.dedup();
+                for raw in values.iter() {
+                    *counts.entry(*raw).or_insert(0) += 1;
+                }
+            }
+            State::Metrics(metrics) => {
+                for raw in values.iter() {
+                    metrics.insert(reader.decode(*raw).as_f64(), *raw);
+                }
+            }
+            State::Buckets { buckets, metrics, counts } => {
+                let keys = &mut scratch.keys;
+                keys.clear();
+                for raw in values.iter() {
+                    buckets.keys(reader.decode(*raw), keys);
+                }
+                if keys.is_empty() {
+                    return;
+                }
+                keys.sort_unstable();
+                keys.dedup();
+
+                scratch.metrics.resize_with(metrics.len(), Vec::new);
+                for ((_, metric), values) in metrics.iter().zip(scratch.metrics.iter_mut()) {
+                    metric.read(doc, values);
+                }
+                for key in keys.iter() {
+                    let state = counts.entry(*key).or_insert_with(|| BucketState::new(metrics.len()));
+                    state.doc_count += 1;
+                    for (((_, metric), values), result) in metrics.iter().zip(&scratch.metrics).zip(&mut state.metrics) {
+                        for raw in values {
+                            result.insert(metric.decode(*raw).as_f64(), *raw);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn harvest(self, docs: &[DocId]) -> AggregationResult {
+        match (self.source, self.state) {
+            (Source::Terms(index), _) => AggregationResult::Terms(TermsResult::new(SegmentAggregation::count_terms(&index, docs))),
+            (Source::Values(reader), State::Terms(counts)) => {
+                let buckets = counts
+                    .into_iter()
+                    .map(|(raw, count)| TermsBucket::new(BucketKey::Number(reader.decode(raw)), count))
+                    .collect();
+                AggregationResult::Terms(TermsResult::new(buckets))
+            }
+            (_, State::Metrics(metrics)) => AggregationResult::Metrics(metrics.finalize()),
+            (_, State::Buckets { buckets, metrics, counts }) => {
+                let results = counts
+                    .into_iter()
+                    .map(|(key, state)| {
+                        let aggregations = metrics
+                            .iter()
+                            .zip(state.metrics)
+                            .map(|((name, _), result)| (name.clone(), AggregationResult::Metrics(result.finalize())))
+                            .collect();
+                        buckets.bucket(key, state.doc_count).with_aggregations(aggregations)
+                    })
+                    .collect();
+                buckets.result(BucketsResult::new(results))
+            }
+        }
+    }
+
+    /// Counts how many of the matching docs, which are in ascending order, appear in each term's postings.
+    fn count_terms(index: &InvertedIndexReader, docs: &[DocId]) -> Vec<TermsBucket> {
+        let mut buckets = Vec::new();
+        if docs.is_empty() {
+            return buckets;
+        }
+        let mut terms = index.terms().stream();
+        while terms.advance() {
+            let mut postings = index.read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic);
+            let (mut count, mut i) = (0, 0);
+            while i < docs.len() && postings.advance() {
+                let doc = postings.doc();
+                while i < docs.len() && docs[i] < doc {
+                    i += 1;
+                }
+                if i < docs.len() && docs[i] == doc {
+                    count += 1;
+                }
+            }
+            if count > 0 {
+                let key = String::from_utf8_lossy(terms.key()).into_owned();
+                buckets.push(TermsBucket::new(BucketKey::Text(key), count));
+            }
+        }
+        buckets
+    }
+}
+
+pub struct AggregationsSegment {
+    aggs: Vec<SegmentAggregation>,
+    docs: Vec<DocId>,
+    scratch: Scratch,
+}
+
+impl SegmentCollector for AggregationsSegment {
+    type Fruit = BTreeMap<String, AggregationResult>;
+
+    fn collect(&mut self, doc: DocId, _: Score) {
+        for agg in &mut self.aggs {
+            agg.collect(doc, &mut self.scratch);
+        }
+        if self.aggs.iter().any(|agg| match agg.source {
+            Source::Terms(_) => true,
+            Source::Values(_) => false,
+        }) {
+            self.docs.push(doc);
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        let docs = self.docs;
+        self.aggs.into_iter().map(|agg| (agg.name.clone(), agg.harvest(&docs))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use tantivy::chrono::{TimeZone, Utc};
+    use tantivy::query::AllQuery;
+    use tantivy::schema::{Schema, FAST, STORED};
+    use tantivy::{doc, Index};
+
+    use super::*;
+    use crate::index::tests::create_sortable_index;
+
+    fn metrics(result: &AggregationResult) -> &MetricsResult {
+        match result {
+            AggregationResult::Metrics(metrics) => metrics,
+            other => panic!("Expected metrics, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_terms_and_metrics() {
+        let index = create_sortable_index();
+        let schema = index.schema();
+        let i64_field = schema.get_field("test_i64").unwrap();
+        let text = schema.get_field("test_text").unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let collector = Aggregations::new()
+            .terms("years", i64_field, 2)
+            .terms("words", text, 2)
+            .metrics("stats", i64_field);
+        let results = searcher.search(&AllQuery, &collector).unwrap();
+
+        let years = TermsResult {
+            buckets: vec![
+                TermsBucket::new(BucketKey::Number(SortValue::I64(-2017)), 1),
+                TermsBucket::new(BucketKey::Number(SortValue::I64(-2015)), 1),
+            ],
+            sum_other_doc_count: 3,
+        };
+        assert_eq!(results["years"], AggregationResult::Terms(years));
+
+        let words = TermsResult {
+            buckets: vec![
+                TermsBucket::new(BucketKey::Text("test".into()), 5),
+                TermsBucket::new(BucketKey::Text("document".into()), 3),
+            ],
+            sum_other_doc_count: 7,
+        };
+        assert_eq!(results["words"], AggregationResult::Terms(words));
+
+        let stats = metrics(&results["stats"]);
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.min, Some(-2017.0));
+        assert_eq!(stats.max, Some(2018.0));
+        assert_eq!(stats.sum, 2016.0);
+        assert_eq!(stats.cardinality, 5);
+    }
+
+    #[test]
+    fn test_histogram_and_range() {
+        let index = create_sortable_index();
+        let schema = index.schema();
+        let u64_field = schema.get_field("test_u64").unwrap();
+        let i64_field = schema.get_field("test_i64").unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let ranges = vec![AggregationRange::new(None, Some(0.0)), AggregationRange::new(Some(0.0), None)];
+        let collector = Aggregations::new()
+            .buckets("hist", u64_field, Buckets::Histogram(2.0), Vec::new())
+            .buckets("signs", i64_field, Buckets::Range(ranges), vec![("values".into(), u64_field)]);
+        let results = searcher.search(&AllQuery, &collector).unwrap();
+
+        let hist = BucketsResult::new(vec![
+            Bucket::new(BucketKey::Number(SortValue::F64(10.0)), 2),
+            Bucket::new(BucketKey::Number(SortValue::F64(12.0)), 2),
+            Bucket::new(BucketKey::Number(SortValue::F64(14.0)), 1),
+        ]);
+        assert_eq!(results["hist"], AggregationResult::Histogram(hist));
+
+        let signs = match &results["signs"] {
+            AggregationResult::Range(signs) => signs,
+            other => panic!("Expected range, got {:?}", other),
+        };
+        let keys: Vec<(BucketKey, u64)> = signs.buckets.iter().map(|b| (b.key.clone(), b.doc_count)).collect();
+        assert_eq!(keys, vec![(BucketKey::Text("*-0".into()), 2), (BucketKey::Text("0-*".into()), 3)]);
+        assert_eq!(metrics(&signs.buckets[0].aggregations["values"]).sum, 24.0);
+        assert_eq!(metrics(&signs.buckets[1].aggregations["values"]).sum, 36.0);
+    }
+
+    #[test]
+    fn test_date_histogram_from_store() {
+        let mut builder = Schema::builder();
+        let date = builder.add_date_field("date", STORED);
+        let value = builder.add_u64_field("value", FAST);
+        let index = Index::create_in_ram(builder.build());
+        let mut writer = index.writer_with_num_threads(1, 30_000_000).unwrap();
+        writer.add_document(doc!(date => Utc.ymd(2019, 8, 14).and_hms(13, 45, 30), value => 1u64));
+        writer.add_document(doc!(date => Utc.ymd(2019, 8, 30).and_hms(0, 0, 0), value => 2u64));
+        writer.add_document(doc!(date => Utc.ymd(2019, 10, 1).and_hms(9, 0, 0), value => 4u64));
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let collector = Aggregations::new().buckets(
+            "monthly",
+            date,
+            Buckets::DateHistogram(CalendarInterval::Month),
+            vec![("values".into(), value)],
+        );
+        let results = searcher.search(&AllQuery, &collector).unwrap();
+        let monthly = match &results["monthly"] {
+            AggregationResult::DateHistogram(monthly) => monthly,
+            other => panic!("Expected date histogram, got {:?}", other),
+        };
+        let keys: Vec<(Option<&str>, u64, f64)> = monthly
+            .buckets
+            .iter()
+            .map(|b| (b.key_as_string.as_deref(), b.doc_count, metrics(&b.aggregations["values"]).sum))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![(Some("2019-08-01T00:00:00Z"), 2, 3.0), (Some("2019-10-01T00:00:00Z"), 1, 4.0)]
+        );
+    }
+}
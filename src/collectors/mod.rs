@@ -0,0 +1,7 @@
+//! Collectors Toshi needs on top of the ones tantivy ships with.
+
+pub use self::aggregations::{Aggregations, Buckets};
+pub use self::top_hits::TopHits;
+
+mod aggregations;
+mod top_hits;
@@ -0,0 +1,261 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::fastfield::FastFieldReader;
+use tantivy::schema::{Field, FieldType, Value};
+use tantivy::store::StoreReader;
+use tantivy::{DocId, Score, SegmentLocalId, SegmentReader, TantivyError};
+
+use toshi_types::query::{Cursor, Order, SortValue};
+
+/// Collects the best `limit` hits in the order described by [`Cursor`]. Unlike tantivy's `TopDocs`
+/// ties are always broken the same way, which is what makes paging through results stable. When a
+/// `search_after` cursor is given only the hits that come after it are collected.
+///
+/// Hits can also be ordered by any number of u64, i64 or f64 fast fields. tantivy's own
+/// `order_by_u64_field` only handles a single field in descending order with no tie breaking.
+/// Dates can't be fast fields, so stored date fields are read from the doc store instead, which is
+/// much slower than sorting on the timestamp indexed as an i64 fast field.
+///
+/// Cursors are tagged with the name of the index being searched so hits from several indexes can
+/// be merged and paged through together.
+pub struct TopHits {
+    limit: usize,
+    after: Option<Cursor>,
+    index: Option<String>,
+    sort: Vec<Field>,
+    orders: Arc<Vec<Order>>,
+}
+
+impl TopHits {
+    pub fn new(limit: usize, after: Option<Cursor>) -> Self {
+        Self {
+            limit,
+            after,
+            index: None,
+            sort: Vec::new(),
+            orders: Arc::new(Vec::new()),
+        }
+    }
+
+    /// The fields must be single valued fast fields or stored date fields, which the caller is expected
+    /// to have checked.
+    pub fn order_by(mut self, sort: Vec<(Field, Order)>) -> Self {
+        let (fields, orders) = sort.into_iter().unzip();
+        self.sort = fields;
+        self.orders = Arc::new(orders);
+        self
+    }
+
+    pub fn in_index(mut self, index: String) -> Self {
+        self.index = Some(index);
+        self
+    }
+}
+
+impl Collector for TopHits {
+    type Fruit = Vec<Cursor>;
+    type Child = TopHitsSegment;
+
+    fn for_segment(&self, segment_local_id: SegmentLocalId, reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        let readers = self
+            .sort
+            .iter()
+            .map(|field| {
+                SortReader::open(reader, *field)
+                    .ok_or_else(|| TantivyError::SchemaError(format!("Field {:?} is not a fast field or a date field", field)))
+            })
+            .collect::<tantivy::Result<Vec<_>>>()?;
+
+        Ok(TopHitsSegment {
+            segment: segment_local_id,
+            limit: self.limit,
+            after: self.after.clone(),
+            index: self.index.clone(),
+            readers,
+            orders: Arc::clone(&self.orders),
+            heap: BinaryHeap::with_capacity(self.limit),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, segment_fruits: Vec<Vec<Cursor>>) -> tantivy::Result<Vec<Cursor>> {
+        let mut hits: Vec<Cursor> = segment_fruits.into_iter().flatten().collect();
+        hits.sort_by(|a, b| a.compare(b, &self.orders));
+        hits.truncate(self.limit);
+        Ok(hits)
+    }
+}
+
+enum SortReader {
+    U64(FastFieldReader<u64>),
+    I64(FastFieldReader<i64>),
+    F64(FastFieldReader<f64>),
+    Date(StoreReader, Field),
+}
+
+impl SortReader {
+    fn open(reader: &SegmentReader, field: Field) -> Option<Self> {
+        let fast_fields = reader.fast_fields();
+        fast_fields
+            .u64(field)
+            .map(SortReader::U64)
+            .or_else(|| fast_fields.i64(field).map(SortReader::I64))
+            .or_else(|| fast_fields.f64(field).map(SortReader::F64))
+            .or_else(|| match reader.schema().get_field_entry(field).field_type() {
+                FieldType::Date(_) => Some(SortReader::Date(reader.get_store_reader(), field)),
+                _ => None,
+            })
+    }
+
+    /// Dates are sorted by their first value as a timestamp in seconds. A document without one sorts
+    /// as the epoch, the same way a fast field reads a missing value as zero.
+    fn get(&self, doc: DocId) -> SortValue {
+        match self {
+            SortReader::U64(r) => SortValue::U64(r.get(doc)),
+            SortReader::I64(r) => SortValue::I64(r.get(doc)),
+            SortReader::F64(r) => SortValue::F64(r.get(doc)),
+            SortReader::Date(store, field) => {
+                let timestamp = store.get(doc).ok().and_then(|stored| match stored.get_first(*field) {
+                    Some(Value::Date(d)) => Some(d.timestamp()),
+                    _ => None,
+                });
+                SortValue::I64(timestamp.unwrap_or(0))
+            }
+        }
+    }
+}
+
+/// A cursor paired with the sort orders needed to rank it, so hits can be kept in a `BinaryHeap`.
+struct Ranked {
+    cursor: Cursor,
+    orders: Arc<Vec<Order>>,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cursor.compare(&other.cursor, &self.orders)
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+pub struct TopHitsSegment {
+    segment: SegmentLocalId,
+    limit: usize,
+    after: Option<Cursor>,
+    index: Option<String>,
+    readers: Vec<SortReader>,
+    orders: Arc<Vec<Order>>,
+    heap: BinaryHeap<Ranked>,
+}
+
+impl SegmentCollector for TopHitsSegment {
+    type Fruit = Vec<Cursor>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        if self.limit == 0 {
+            return;
+        }
+        let sort = self.readers.iter().map(|r| r.get(doc)).collect();
+        let mut hit = Ranked {
+            cursor: Cursor::new(score, self.segment, doc).with_sort(sort),
+            orders: Arc::clone(&self.orders),
+        };
+        // Every hit collected here shares the same index, so it only needs setting to compare
+        // against a cursor that may be from another index, the rest are tagged on harvest.
+        if let Some(after) = &self.after {
+            hit.cursor.index = self.index.clone();
+            if !after.is_before(&hit.cursor, &self.orders) {
+                return;
+            }
+        }
+        if self.heap.len() < self.limit {
+            self.heap.push(hit);
+        } else if let Some(mut worst) = self.heap.peek_mut() {
+            if hit < *worst {
+                *worst = hit;
+            }
+        }
+    }
+
+    fn harvest(self) -> Vec<Cursor> {
+        let index = self.index;
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|r| r.cursor.with_index(index.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use tantivy::chrono::{TimeZone, Utc};
+    use tantivy::query::AllQuery;
+    use tantivy::schema::{Schema, STORED};
+    use tantivy::{doc, Index};
+
+    use super::*;
+    use crate::index::tests::create_sortable_index;
+
+    #[test]
+    fn test_ties_and_search_after() {
+        let index = toshi_test::create_test_index();
+        let searcher = index.reader().unwrap().searcher();
+
+        let first = searcher.search(&AllQuery, &TopHits::new(2, None)).unwrap();
+        assert_eq!(first, vec![Cursor::new(1.0, 0, 0), Cursor::new(1.0, 0, 1)]);
+
+        let rest = searcher.search(&AllQuery, &TopHits::new(10, first.last().cloned())).unwrap();
+        let docs: Vec<u32> = rest.iter().map(|c| c.doc).collect();
+        assert_eq!(docs, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_order_by_fast_field() {
+        let index = create_sortable_index();
+        let field = index.schema().get_field("test_i64").unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let collector = TopHits::new(3, None).order_by(vec![(field, Order::Desc)]);
+        let hits = searcher.search(&AllQuery, &collector).unwrap();
+        let values: Vec<SortValue> = hits.into_iter().flat_map(|c| c.sort).collect();
+        assert_eq!(values, vec![SortValue::I64(2018), SortValue::I64(2016), SortValue::I64(2014)]);
+    }
+
+    #[test]
+    fn test_order_by_stored_date() {
+        let mut builder = Schema::builder();
+        let date = builder.add_date_field("date", STORED);
+        let index = Index::create_in_ram(builder.build());
+        let mut writer = index.writer_with_num_threads(1, 30_000_000).unwrap();
+        writer.add_document(doc!(date => Utc.ymd(2019, 8, 14).and_hms(13, 45, 30)));
+        writer.add_document(doc!(date => Utc.ymd(2018, 1, 1).and_hms(0, 0, 0)));
+        writer.add_document(doc!(date => Utc.ymd(2019, 10, 1).and_hms(9, 0, 0)));
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let collector = TopHits::new(3, None).order_by(vec![(date, Order::Asc)]);
+        let docs: Vec<u32> = searcher.search(&AllQuery, &collector).unwrap().iter().map(|c| c.doc).collect();
+        assert_eq!(docs, vec![1, 0, 2]);
+    }
+}
@@ -13,15 +13,12 @@ pub fn watcher(cat: SharedCatalog, commit_duration: u64, lock: Arc<AtomicBool>)
         .for_each(move |_| {
             let cat = cat.read();
             cat.get_collection().into_iter().for_each(|(key, index)| {
-                let writer = index.get_writer();
                 let current_ops = index.get_opstamp();
                 if current_ops == 0 {
                     debug!("No update to index={}, opstamp={}", key, current_ops);
                 } else if !lock.load(Ordering::SeqCst) {
-                    let mut w = writer.write();
                     debug!("Committing {}...", key);
-                    w.commit().unwrap();
-                    index.set_opstamp(0);
+                    index.commit().unwrap();
                 }
             });
             Ok(())
@@ -1,26 +1,40 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use parking_lot::RwLock;
-use tantivy::collector::{FacetCollector, MultiCollector, TopDocs};
-use tantivy::query::{AllQuery, QueryParser};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Count, FacetCollector, MultiCollector, TopDocs};
+use tantivy::directory::error::OpenReadError;
+use tantivy::query::{AllQuery, Query as TantivyQuery, QueryParser, TermQuery};
+use tantivy::schema::Value;
 use tantivy::schema::*;
 use tantivy::space_usage::SearcherSpaceUsage;
-use tantivy::{Document, Index, IndexReader, IndexWriter, ReloadPolicy, Term};
+use tantivy::{
+    Directory, DocAddress, DocSet, Document, Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, SegmentId, SkipResult,
+    SnippetGenerator, TantivyError, Term,
+};
 use tokio::prelude::*;
 use tracing::*;
 
-use toshi_types::client::ScoredDoc;
+use toshi_types::client::{ExplainResult, ScoredDoc};
 use toshi_types::error::Error;
-use toshi_types::query::{CreateQuery, KeyValue, Query, Search};
-use toshi_types::server::{DeleteDoc, DocsAffected};
+use toshi_types::query::{Aggregation, CreateQuery, Cursor, FacetQuery, Highlight, KeyValue, Order, Query, Search, SortField};
+use toshi_types::server::{DeleteDoc, DocsAffected, FieldUpdate, IndexOptions, IndexSettings, UpdateMode};
 
+use crate::collectors::{Aggregations, Buckets, TopHits};
+use crate::mapping;
 use crate::settings::Settings;
 use crate::Result;
-use crate::{AddDocument, SearchResults};
+use crate::{AddDocument, NamedDoc, SearchResults, UpdateDocument};
 
+/// Name of the file Toshi keeps its own per index settings in. The leading dot keeps tantivy's
+/// managed directory from ever garbage collecting it.
+const SETTINGS_FILE: &str = ".toshi.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum IndexLocation {
     LOCAL,
     REMOTE,
@@ -38,6 +52,35 @@ pub trait IndexHandle {
     fn delete_term(&self, term: DeleteDoc) -> Self::DeleteResponse;
 }
 
+/// Primary keys added or deleted since the last commit, which the reader can't see yet. Together
+/// with the reader they tell whether a key is in the index without committing first.
+#[derive(Default)]
+struct PendingKeys {
+    added: HashSet<Term>,
+    deleted: HashSet<Term>,
+}
+
+impl PendingKeys {
+    fn add(&mut self, term: Term) {
+        self.deleted.remove(&term);
+        self.added.insert(term);
+    }
+
+    fn delete(&mut self, term: Term) {
+        self.added.remove(&term);
+        self.deleted.insert(term);
+    }
+
+    fn touches(&self, term: &Term) -> bool {
+        self.added.contains(term) || self.deleted.contains(term)
+    }
+
+    fn clear(&mut self) {
+        self.added.clear();
+        self.deleted.clear();
+    }
+}
+
 /// Index handle that operates on an Index local to the node, a remote index handle
 /// will eventually call to wherever the local index is stored, so at some level the relevant
 /// local handle will always get called through rpc
@@ -47,7 +90,9 @@ pub struct LocalIndex {
     reader: IndexReader,
     current_opstamp: Arc<AtomicUsize>,
     deleted_docs: Arc<AtomicU64>,
+    pending_keys: Arc<Mutex<PendingKeys>>,
     settings: Settings,
+    index_settings: IndexSettings,
     name: String,
 }
 
@@ -59,7 +104,9 @@ impl Clone for LocalIndex {
             reader: self.reader.clone(),
             current_opstamp: Arc::clone(&self.current_opstamp),
             deleted_docs: Arc::clone(&self.deleted_docs),
+            pending_keys: Arc::clone(&self.pending_keys),
             settings: self.settings.clone(),
+            index_settings: self.index_settings.clone(),
             name: self.name.clone(),
         }
     }
@@ -93,88 +140,13 @@ impl IndexHandle for LocalIndex {
     }
 
     fn search_index(&self, search: Search) -> Self::SearchResponse {
-        let searcher = self.reader.searcher();
-        let schema = self.index.schema();
-        let collector = TopDocs::with_limit(search.limit);
-        let mut multi_collector = MultiCollector::new();
-
-        let top_handle = multi_collector.add_collector(collector);
-        let facet_handle = search.facets.clone().and_then(|f| {
-            if let Some(field) = schema.get_field(&f.get_facets_fields()) {
-                let mut col = FacetCollector::for_field(field);
-                for term in f.get_facets_values() {
-                    col.add_facet(&term);
-                }
-                Some(multi_collector.add_collector(col))
-            } else {
-                None
-            }
-        });
-
-        if let Some(query) = search.query {
-            let gen_query = match query {
-                Query::Regex(regex) => regex.create_query(&schema)?,
-                Query::Phrase(phrase) => phrase.create_query(&schema)?,
-                Query::Fuzzy(fuzzy) => fuzzy.create_query(&schema)?,
-                Query::Exact(term) => term.create_query(&schema)?,
-                Query::Range(range) => range.create_query(&schema)?,
-                Query::Boolean { bool } => bool.create_query(&schema)?,
-                Query::Raw { raw } => {
-                    let fields: Vec<Field> = schema.fields().iter().filter_map(|e| schema.get_field(e.name())).collect();
-                    let query_parser = QueryParser::for_index(&self.index, fields);
-                    query_parser.parse_query(&raw)?
-                }
-                Query::All => Box::new(AllQuery),
-            };
-
-            debug!("{:?}", gen_query);
-            let mut scored_docs = searcher.search(&*gen_query, &multi_collector)?;
-
-            let docs: Vec<ScoredDoc<BTreeMap<_, _>>> = top_handle
-                .extract(&mut scored_docs)
-                .into_iter()
-                .map(|(score, doc)| {
-                    let d = searcher.doc(doc).expect("Doc not found in segment");
-                    ScoredDoc::<BTreeMap<_, _>>::new(Some(score), schema.to_named_doc(&d).0)
-                })
-                .collect();
-
-            if let Some(facets) = facet_handle {
-                if let Some(t) = &search.facets {
-                    let facet_counts = facets
-                        .extract(&mut scored_docs)
-                        .get(&t.get_facets_values()[0])
-                        .map(|(f, c)| KeyValue::new(f.to_string(), c))
-                        .collect();
-                    return Ok(SearchResults::with_facets(docs, facet_counts));
-                }
-            }
-            Ok(SearchResults::new(docs))
-        } else {
-            Err(Error::QueryError("Empty Query Provided".into()))
-        }
+        LocalIndex::search_with(&self.name, &self.reader.searcher(), search)
     }
 
     fn add_document(&self, add_doc: AddDocument) -> Self::AddResponse {
-        let index_schema = self.index.schema();
-        let writer_lock = self.get_writer();
-        {
-            let index_writer = writer_lock.read();
-            let doc: Document = LocalIndex::parse_doc(&index_schema, &add_doc.document.to_string())?;
-            index_writer.add_document(doc);
-        }
-        if let Some(opts) = add_doc.options {
-            if opts.commit {
-                let mut commit_writer = writer_lock.write();
-                commit_writer.commit()?;
-                self.set_opstamp(0);
-            } else {
-                self.set_opstamp(self.get_opstamp() + 1);
-            }
-        } else {
-            self.set_opstamp(self.get_opstamp() + 1);
-        }
-        Ok(())
+        let doc: Document = LocalIndex::parse_doc(&self.index.schema(), &add_doc.document.to_string())?;
+        self.add_unique(doc)?;
+        self.commit_if_requested(&add_doc.options)
     }
 
     fn delete_term(&self, term: DeleteDoc) -> Self::DeleteResponse {
@@ -188,18 +160,15 @@ impl IndexHandle for LocalIndex {
             for (field, value) in term.terms {
                 if let Some(f) = index_schema.get_field(&field) {
                     let term = Term::from_field_text(f, &value);
+                    if Some(f) == self.primary_key() {
+                        self.pending_keys.lock().delete(term.clone());
+                    }
                     index_writer.delete_term(term);
                 }
             }
         }
-        if let Some(opts) = term.options {
-            if opts.commit {
-                let mut commit_writer = writer_lock.write();
-                commit_writer.commit()?;
-                self.set_opstamp(0);
-            }
-        }
-        let docs_affected = before - self.reader.searcher().num_docs();
+        self.commit_if_requested(&term.options)?;
+        let docs_affected = before.saturating_sub(self.reader.searcher().num_docs());
         let current = self.deleted_docs.load(Ordering::SeqCst);
         self.deleted_docs.store(current + docs_affected, Ordering::SeqCst);
         Ok(DocsAffected { docs_affected })
@@ -207,7 +176,157 @@ impl IndexHandle for LocalIndex {
 }
 
 impl LocalIndex {
+    /// Runs a search of the index `name` on the given searcher rather than the latest one, so scrolls
+    /// can keep reading from the snapshot they were opened on.
+    pub fn search_with(name: &str, searcher: &Searcher, search: Search) -> Result<SearchResults> {
+        let schema = searcher.schema();
+        let sort = LocalIndex::sort_fields(schema, &search.sort)?;
+        let projection = LocalIndex::projection(schema, &search)?;
+        let aggregations = LocalIndex::aggregations(schema, &search.aggs)?;
+        let collector = TopHits::new(search.window()?, search.search_after.clone())
+            .order_by(sort)
+            .in_index(name.into());
+        let mut multi_collector = MultiCollector::new();
+
+        let top_handle = multi_collector.add_collector(collector);
+        let count_handle = multi_collector.add_collector(Count);
+        let agg_handle = if aggregations.is_empty() {
+            None
+        } else {
+            Some(multi_collector.add_collector(aggregations))
+        };
+        let facet_handles = LocalIndex::facet_fields(schema, &search.facets)?
+            .into_iter()
+            .map(|(name, field, paths)| {
+                let mut col = FacetCollector::for_field(field);
+                for path in &paths {
+                    col.add_facet(path.as_str());
+                }
+                (name, paths, multi_collector.add_collector(col))
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(query) = search.query.clone() {
+            let gen_query = LocalIndex::create_query(searcher.index(), query)?;
+
+            debug!("{:?}", gen_query);
+            let mut scored_docs = searcher.search(&*gen_query, &multi_collector)?;
+            let cursors = top_handle.extract(&mut scored_docs).into_iter().skip(search.from);
+            let docs = LocalIndex::fetch_hits(searcher, &*gen_query, &search, &projection, cursors)?;
+
+            let total_hits = count_handle.extract(&mut scored_docs);
+            let aggs = agg_handle.map(|h| h.extract(&mut scored_docs)).unwrap_or_default();
+            let facets = facet_handles
+                .into_iter()
+                .map(|(name, paths, handle)| {
+                    let counts = handle.extract(&mut scored_docs);
+                    let facet_counts = paths
+                        .iter()
+                        .flat_map(|path| counts.get(path.as_str()))
+                        .map(|(f, c)| KeyValue::new(f.to_string(), c))
+                        .collect();
+                    (name, facet_counts)
+                })
+                .collect();
+            Ok(SearchResults::new(docs)
+                .with_facet_fields(facets)
+                .with_total(total_hits)
+                .with_aggregations(aggs))
+        } else {
+            Err(Error::QueryError("Empty Query Provided".into()))
+        }
+    }
+
+    /// Reads the next `search.limit` hits of an unsorted search of the index `name` in index order,
+    /// starting at `position` and moving it past them. Unlike `search_with` only the hits returned are
+    /// scored, rather than every hit of the search, so reading a whole index in batches this way takes
+    /// a single pass over it. Hits carry their score but aren't ordered by it. `from` skips hits the same
+    /// way it does for a search, aggregations and facets are left to `search_with`.
+    pub fn scan(name: &str, searcher: &Searcher, search: &Search, position: &mut DocAddress) -> Result<Vec<ScoredDoc<NamedDoc>>> {
+        let projection = LocalIndex::projection(searcher.schema(), search)?;
+        let query = LocalIndex::create_query(searcher.index(), search.query.clone().unwrap_or(Query::All))?;
+        let weight = query.weight(searcher, true)?;
+        let wanted = search.window()?;
+        let DocAddress(start_segment, start_doc) = *position;
+        let mut cursors = Vec::new();
+        *position = DocAddress(searcher.segment_readers().len() as u32, 0);
+        for (segment, reader) in searcher.segment_readers().iter().enumerate().skip(start_segment as usize) {
+            let segment = segment as u32;
+            let mut scorer = weight.scorer(reader)?;
+            let mut found = scorer.advance();
+            if found && segment == start_segment && scorer.doc() < start_doc {
+                found = scorer.skip_next(start_doc) != SkipResult::End;
+            }
+            while found && cursors.len() < wanted {
+                if !reader.is_deleted(scorer.doc()) {
+                    cursors.push(Cursor::new(scorer.score(), segment, scorer.doc()).with_index(Some(name.into())));
+                }
+                found = scorer.advance();
+            }
+            if cursors.len() == wanted {
+                if found {
+                    *position = DocAddress(segment, scorer.doc());
+                } else {
+                    *position = DocAddress(segment + 1, 0);
+                }
+                break;
+            }
+        }
+        LocalIndex::fetch_hits(searcher, &*query, search, &projection, cursors.into_iter().skip(search.from))
+    }
+
+    /// Loads the stored fields of each hit, along with its highlights and explanation if the search
+    /// asked for them.
+    fn fetch_hits<I: Iterator<Item = Cursor>>(
+        searcher: &Searcher,
+        query: &dyn TantivyQuery,
+        search: &Search,
+        projection: &Option<HashSet<Field>>,
+        cursors: I,
+    ) -> Result<Vec<ScoredDoc<NamedDoc>>> {
+        let (highlight, explain) = (&search.highlight, search.explain);
+        let highlighters = match highlight {
+            Some(highlight) => LocalIndex::highlighters(searcher, query, highlight)?,
+            None => Vec::new(),
+        };
+        cursors
+            .map(|cursor| {
+                let addr = DocAddress(cursor.segment, cursor.doc);
+                let d = searcher.doc(addr)?;
+                let highlights = highlighters
+                    .iter()
+                    .filter_map(|(name, generator)| {
+                        let snippet = generator.snippet_from_doc(&d);
+                        if snippet.highlighted().is_empty() {
+                            return None;
+                        }
+                        highlight.as_ref().map(|h| (name.clone(), h.render(&snippet)))
+                    })
+                    .collect();
+                let explanation = if explain {
+                    LocalIndex::explanation(query, searcher, addr)?
+                } else {
+                    None
+                };
+                let scored = ScoredDoc::<NamedDoc>::with_cursor(cursor, LocalIndex::project(searcher.schema(), &d, projection));
+                Ok(scored.with_highlights(highlights).with_explanation(explanation))
+            })
+            .collect()
+    }
+
+    /// A reader pinned to the segments searchable right now. It is never reloaded, so it keeps
+    /// seeing the same documents however the index changes afterwards.
+    pub fn snapshot(&self) -> Result<IndexReader> {
+        self.index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .num_searchers(1)
+            .try_into()
+            .map_err(Into::into)
+    }
+
     pub fn new(index: Index, settings: Settings, name: &str) -> Result<Self> {
+        let index_settings = LocalIndex::read_settings(&index)?;
         let i = index.writer(settings.writer_memory)?;
         i.set_merge_policy(settings.get_merge_policy());
         let current_opstamp = Arc::new(AtomicUsize::new(0));
@@ -219,13 +338,682 @@ impl LocalIndex {
             writer,
             current_opstamp,
             deleted_docs: Arc::new(AtomicU64::new(0)),
+            pending_keys: Arc::new(Mutex::new(PendingKeys::default())),
             settings,
+            index_settings,
             name: name.into(),
         })
     }
 
+    pub fn read_settings(index: &Index) -> Result<IndexSettings> {
+        match index.directory().atomic_read(Path::new(SETTINGS_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(Into::into),
+            Err(OpenReadError::FileDoesNotExist(_)) => Ok(IndexSettings::default()),
+            Err(e) => Err(Error::IOError(e.to_string())),
+        }
+    }
+
+    pub fn write_settings(index: &Index, index_settings: &IndexSettings) -> Result<()> {
+        index_settings.validate(&index.schema())?;
+        let bytes = serde_json::to_vec(index_settings)?;
+        let mut dir = index.directory().clone();
+        dir.atomic_write(Path::new(SETTINGS_FILE), &bytes).map_err(Into::into)
+    }
+
+    pub fn index_settings(&self) -> &IndexSettings {
+        &self.index_settings
+    }
+
+    pub fn primary_key(&self) -> Option<Field> {
+        self.index_settings
+            .primary_key
+            .as_ref()
+            .and_then(|k| self.index.schema().get_field(k))
+    }
+
+    /// Parses a document id given as a string into a value of the primary key field's type.
+    fn key_value(&self, id: &str) -> Result<(Field, Value)> {
+        let name = self.index_settings.primary_key.clone().unwrap_or_default();
+        let key = self
+            .primary_key()
+            .ok_or_else(|| Error::InvalidPrimaryKey(format!("index {} has no primary key", self.name)))?;
+        let bad_id = |_| Error::InvalidPrimaryKey(format!("'{}' is not a valid value for '{}'", id, name));
+        let value = match self.index.schema().get_field_entry(key).field_type() {
+            FieldType::U64(_) => id.parse::<u64>().map(Value::U64).map_err(bad_id)?,
+            FieldType::I64(_) => id.parse::<i64>().map(Value::I64).map_err(bad_id)?,
+            _ => Value::Str(id.into()),
+        };
+        Ok((key, value))
+    }
+
+    /// Builds the term for a document id given as a string, parsing it to the type of the primary key field.
+    pub fn key_term(&self, id: &str) -> Result<Term> {
+        let (key, value) = self.key_value(id)?;
+        LocalIndex::value_term(key, &value).ok_or_else(|| Error::InvalidPrimaryKey(format!("'{}' can not be used as an id", id)))
+    }
+
+    fn value_term(field: Field, value: &Value) -> Option<Term> {
+        match value {
+            Value::Str(s) => Some(Term::from_field_text(field, s)),
+            Value::U64(v) => Some(Term::from_field_u64(field, *v)),
+            Value::I64(v) => Some(Term::from_field_i64(field, *v)),
+            _ => None,
+        }
+    }
+
+    fn value_string(value: &Value) -> String {
+        match value {
+            Value::Str(s) => s.clone(),
+            Value::U64(v) => v.to_string(),
+            Value::I64(v) => v.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// The primary key term of a document, which every document of an index with a key must have.
+    fn doc_key(&self, doc: &Document) -> Result<Option<Term>> {
+        let key = match self.primary_key() {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let term = doc.get_first(key).and_then(|v| LocalIndex::value_term(key, v)).ok_or_else(|| {
+            let name = self.index.schema().get_field_name(key).to_string();
+            Error::InvalidPrimaryKey(format!("document is missing key field '{}'", name))
+        })?;
+        Ok(Some(term))
+    }
+
+    /// Whether a document with the key is in the index, going by the keys pending since the last commit
+    /// before asking the reader. The writer has to be held so no commit can clear `pending` meanwhile.
+    fn key_exists(&self, pending: &PendingKeys, term: &Term) -> Result<bool> {
+        if pending.added.contains(term) {
+            return Ok(true);
+        }
+        if pending.deleted.contains(term) {
+            return Ok(false);
+        }
+        let query = TermQuery::new(term.clone(), IndexRecordOption::Basic);
+        let count = self.reader.searcher().search(&query, &Count)?;
+        Ok(count > 0)
+    }
+
+    /// Adds a document, rejecting it if an index with a primary key already has a document with its key.
+    /// The check and the add happen while holding the pending keys, so a concurrent add can't slip a
+    /// duplicate in, and the writer, so a commit can't either. Adds only wait on each other for the
+    /// key lookup, never on a commit.
+    pub fn add_unique(&self, doc: Document) -> Result<()> {
+        let index_writer = self.writer.read();
+        let term = match self.doc_key(&doc)? {
+            Some(term) => term,
+            None => {
+                index_writer.add_document(doc);
+                return Ok(());
+            }
+        };
+        let mut pending = self.pending_keys.lock();
+        if self.key_exists(&pending, &term)? {
+            let id = doc.get_first(term.field()).map(LocalIndex::value_string).unwrap_or_default();
+            return Err(Error::DuplicateKey(id));
+        }
+        index_writer.add_document(doc);
+        pending.add(term);
+        Ok(())
+    }
+
+    fn find_document(&self, searcher: &Searcher, id: &str) -> Result<Option<DocAddress>> {
+        let query = TermQuery::new(self.key_term(id)?, IndexRecordOption::Basic);
+        let top = searcher.search(&query, &TopDocs::with_limit(1))?;
+        Ok(top.into_iter().next().map(|(_, addr)| addr))
+    }
+
+    pub fn get_document(&self, id: &str) -> Result<Option<NamedDoc>> {
+        let searcher = self.reader.searcher();
+        match self.find_document(&searcher, id)? {
+            Some(addr) => {
+                let doc = searcher.doc(addr)?;
+                Ok(Some(self.index.schema().to_named_doc(&doc).0))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Counts the documents matching a query without scoring them or loading any stored fields.
+    pub fn count(&self, query: Query) -> Result<usize> {
+        let query = LocalIndex::create_query(&self.index, query)?;
+        self.reader.searcher().search(&*query, &Count).map_err(Into::into)
+    }
+
+    /// Explains how the document with the given id scores against a query, the explanation is
+    /// left empty when the document doesn't match the query at all.
+    pub fn explain(&self, id: &str, query: Query) -> Result<ExplainResult> {
+        let searcher = self.reader.searcher();
+        let addr = self
+            .find_document(&searcher, id)?
+            .ok_or_else(|| Error::UnknownDocument(id.into()))?;
+        let query = LocalIndex::create_query(&self.index, query)?;
+        let explanation = LocalIndex::explanation(&*query, &searcher, addr)?;
+        Ok(ExplainResult::new(id.into(), explanation))
+    }
+
+    /// tantivy reports a document that doesn't match the query as an invalid argument.
+    fn explanation(query: &dyn TantivyQuery, searcher: &Searcher, addr: DocAddress) -> Result<Option<serde_json::Value>> {
+        match query.explain(searcher, addr) {
+            Ok(explanation) => Ok(Some(serde_json::to_value(&explanation)?)),
+            Err(TantivyError::InvalidArgument(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn create_query(index: &Index, query: Query) -> Result<Box<dyn TantivyQuery>> {
+        let schema = index.schema();
+        let gen_query = match query {
+            Query::Regex(regex) => regex.create_query(&schema)?,
+            Query::Phrase(phrase) => phrase.create_query(&schema)?,
+            Query::Fuzzy(fuzzy) => fuzzy.create_query(&schema)?,
+            Query::Exact(term) => term.create_query(&schema)?,
+            Query::Range(range) => range.create_query(&schema)?,
+            Query::Boolean { bool } => bool.create_query(&schema)?,
+            Query::Raw { raw } => {
+                let fields: Vec<Field> = schema.fields().iter().filter_map(|e| schema.get_field(e.name())).collect();
+                let query_parser = QueryParser::for_index(index, fields);
+                query_parser.parse_query(&raw)?
+            }
+            Query::All => Box::new(AllQuery),
+        };
+        Ok(gen_query)
+    }
+
+    /// Calls `f` with the stored fields of each committed document matching `query`, one at a time
+    /// so a whole index never has to be held in memory.
+    fn for_each_match<F: FnMut(Document) -> Result<()>>(&self, query: Query, mut f: F) -> Result<()> {
+        let query = LocalIndex::create_query(&self.index, query)?;
+        let searcher = self.reader.searcher();
+        let weight = query.weight(&searcher, false)?;
+        for segment_reader in searcher.segment_readers() {
+            let store = segment_reader.get_store_reader();
+            let mut scorer = weight.scorer(segment_reader)?;
+            while scorer.advance() {
+                if !segment_reader.is_deleted(scorer.doc()) {
+                    f(store.get(scorer.doc())?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies every stored document of `source` matching `query` into this index and commits,
+    /// returning how many were copied. Fields are renamed by `rename`, and fields this index's schema
+    /// doesn't have are left out. With a primary key, a copied document replaces any with the same key.
+    pub fn reindex_from(&self, source: &LocalIndex, query: Query, rename: &BTreeMap<String, String>) -> Result<u64> {
+        if source == self {
+            return Err(Error::QueryError(format!("can not reindex {} into itself", self.name)));
+        }
+        let (source_schema, schema) = (source.index.schema(), self.index.schema());
+        LocalIndex::check_copyable(&source_schema, &schema, rename)?;
+        let mut index_writer = self.writer.write();
+        let mut copied = 0;
+        source.for_each_match(query, |doc| {
+            let mut named = serde_json::Map::new();
+            for (field, values) in source_schema.to_named_doc(&doc).0 {
+                let field = rename.get(&field).cloned().unwrap_or(field);
+                if schema.get_field(&field).is_some() {
+                    named.insert(field, serde_json::to_value(values)?);
+                }
+            }
+            let doc = LocalIndex::parse_doc(&schema, &serde_json::Value::Object(named).to_string())?;
+            if let Some(term) = self.doc_key(&doc)? {
+                index_writer.delete_term(term);
+            }
+            index_writer.add_document(doc);
+            copied += 1;
+            Ok(())
+        })?;
+        self.commit_locked(&mut index_writer)?;
+        Ok(copied)
+    }
+
+    /// Refuses a `reindex_from` that would lose data. Only stored fields can be read back, so every
+    /// field of `source` that `dest` has, after renaming, has to be stored.
+    pub fn check_copyable(source: &Schema, dest: &Schema, rename: &BTreeMap<String, String>) -> Result<()> {
+        let unstored: Vec<&str> = source
+            .fields()
+            .iter()
+            .filter(|entry| !entry.is_stored())
+            .map(FieldEntry::name)
+            .filter(|name| dest.get_field(rename.get(*name).map_or(name, String::as_str)).is_some())
+            .collect();
+        if unstored.is_empty() {
+            return Ok(());
+        }
+        Err(Error::QueryError(format!(
+            "can not copy {}, fields have to be stored to be copied",
+            unstored.join(", ")
+        )))
+    }
+
+    /// Commits pending writes, returning the opstamp of the commit.
+    pub fn commit(&self) -> Result<u64> {
+        self.commit_locked(&mut self.writer.write())
+    }
+
+    /// Commits with the writer already held exclusively. The reader is reloaded before the writer is
+    /// released, so keys no longer pending are visible to the next writer.
+    fn commit_locked(&self, index_writer: &mut IndexWriter) -> Result<u64> {
+        let opstamp = index_writer.commit()?;
+        self.reader.reload()?;
+        self.set_opstamp(0);
+        self.pending_keys.lock().clear();
+        Ok(opstamp)
+    }
+
+    /// The committed segments of this index and how many deletes each has. Any write that gets committed
+    /// changes them, so comparing them before and after tells whether a copy of the index is still
+    /// current. A merge changes them as well, which only means the copy is made again.
+    pub fn committed_segments(&self) -> Vec<(SegmentId, u32)> {
+        let mut segments: Vec<_> = self
+            .reader
+            .searcher()
+            .segment_readers()
+            .iter()
+            .map(|segment| (segment.segment_id(), segment.num_deleted_docs()))
+            .collect();
+        segments.sort();
+        segments
+    }
+
+    /// Commits and copies every document as it is into `dest`, whose schema has to start with this
+    /// index's fields. Writes to this index wait until the copy is committed, while searches carry on.
+    /// Returns the `committed_segments` of this index the copy reflects.
+    pub fn copy_into(&self, dest: &Index) -> Result<Vec<(SegmentId, u32)>> {
+        let mut writer = self.writer.write();
+        self.commit_locked(&mut writer)?;
+        let copied = self.committed_segments();
+        let mut dest_writer = dest.writer(self.settings.writer_memory)?;
+        self.for_each_match(Query::All, |doc| {
+            dest_writer.add_document(doc);
+            Ok(())
+        })?;
+        dest_writer.commit()?;
+        dest_writer.wait_merging_threads()?;
+        Ok(copied)
+    }
+
+    /// Deletes every document matching `query` and commits, returning how many were deleted. The writer
+    /// is held throughout and pending writes are committed first, so the documents found are exactly
+    /// the ones removed. tantivy can only delete by term, so this needs a primary key: each matching
+    /// document is deleted by its key, which no other document has.
+    pub fn delete_by_query(&self, query: Query) -> Result<DocsAffected> {
+        let key = self
+            .primary_key()
+            .ok_or_else(|| Error::InvalidPrimaryKey(format!("index {} has no primary key to delete documents by", self.name)))?;
+        let mut index_writer = self.writer.write();
+        self.commit_locked(&mut index_writer)?;
+        let before = self.reader.searcher().num_docs();
+        self.for_each_match(query, |doc| {
+            if let Some(term) = doc.get_first(key).and_then(|v| LocalIndex::value_term(key, v)) {
+                index_writer.delete_term(term);
+            }
+            Ok(())
+        })?;
+        self.commit_locked(&mut index_writer)?;
+        let docs_affected = before.saturating_sub(self.reader.searcher().num_docs());
+        self.deleted_docs.fetch_add(docs_affected, Ordering::SeqCst);
+        Ok(DocsAffected { docs_affected })
+    }
+
+    /// Rewrites every document matching `query` with `updates` applied and commits, counting the
+    /// documents that actually changed. Documents are rebuilt from their stored fields, so this is
+    /// refused when the schema has a field that isn't stored, and each changed document is replaced
+    /// by its primary key, which the index needs. Nothing is changed if any update fails.
+    pub fn update_by_query(&self, query: Query, updates: &BTreeMap<String, FieldUpdate>) -> Result<DocsAffected> {
+        let schema = self.index.schema();
+        let key = self
+            .primary_key()
+            .ok_or_else(|| Error::InvalidPrimaryKey(format!("index {} has no primary key to update documents by", self.name)))?;
+        for field in updates.keys() {
+            match schema.get_field(field) {
+                Some(f) if f == key => return Err(Error::QueryError(format!("the primary key '{}' can not be updated", field))),
+                Some(_) => (),
+                None => return Err(Error::UnknownIndexField(field.clone())),
+            }
+        }
+        self.require_stored("updating by query")?;
+
+        let mut index_writer = self.writer.write();
+        self.commit_locked(&mut index_writer)?;
+        let mut docs_affected = 0;
+        let result = self.for_each_match(query, |doc| {
+            let term = match doc.get_first(key).and_then(|v| LocalIndex::value_term(key, v)) {
+                Some(term) => term,
+                None => return Ok(()),
+            };
+            if let Some(updated) = LocalIndex::updated_doc(&schema, &doc, updates)? {
+                index_writer.delete_term(term);
+                index_writer.add_document(updated);
+                docs_affected += 1;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            index_writer.rollback()?;
+            return Err(e);
+        }
+        self.commit_locked(&mut index_writer)?;
+        Ok(DocsAffected { docs_affected })
+    }
+
+    /// `doc` with `updates` applied, or `None` if they leave it unchanged. A value the field can't
+    /// take is the request's fault, so it's reported as a query error.
+    fn updated_doc(schema: &Schema, doc: &Document, updates: &BTreeMap<String, FieldUpdate>) -> Result<Option<Document>> {
+        let existing = schema.to_named_doc(doc).0;
+        let mut named = serde_json::Map::new();
+        for (field, values) in &existing {
+            named.insert(field.clone(), serde_json::to_value(values)?);
+        }
+        for (field, update) in updates {
+            let value = LocalIndex::apply_update(schema, field, existing.get(field), update)?;
+            named.insert(field.clone(), value);
+        }
+        let updated = LocalIndex::parse_doc(schema, &serde_json::Value::Object(named).to_string())
+            .map_err(|e| Error::QueryError(format!("the updated document is invalid: {}", e)))?;
+        if schema.to_named_doc(&updated).0 == existing {
+            return Ok(None);
+        }
+        Ok(Some(updated))
+    }
+
+    /// The new JSON value of `field` once `update` is applied to its current values.
+    fn apply_update(schema: &Schema, field: &str, current: Option<&Vec<Value>>, update: &FieldUpdate) -> Result<serde_json::Value> {
+        let amount = match update {
+            FieldUpdate::Set(value) => return Ok(value.clone()),
+            FieldUpdate::Increment(amount) => amount,
+        };
+        let first = current.and_then(|values| values.first());
+        let bad_amount = || Error::QueryError(format!("can not increment '{}' by {}", field, amount));
+        let field_type = schema.get_field(field).map(|f| schema.get_field_entry(f).field_type().clone());
+        let value = match field_type {
+            Some(FieldType::U64(_)) => {
+                let current = match first {
+                    Some(Value::U64(v)) => *v,
+                    _ => 0,
+                };
+                let amount = amount.as_i64().ok_or_else(bad_amount)?;
+                let next = if amount < 0 {
+                    current.checked_sub(amount.wrapping_neg() as u64)
+                } else {
+                    current.checked_add(amount as u64)
+                };
+                serde_json::Value::from(next.ok_or_else(bad_amount)?)
+            }
+            Some(FieldType::I64(_)) => {
+                let current = match first {
+                    Some(Value::I64(v)) => *v,
+                    _ => 0,
+                };
+                let amount = amount.as_i64().ok_or_else(bad_amount)?;
+                serde_json::Value::from(current.checked_add(amount).ok_or_else(bad_amount)?)
+            }
+            Some(FieldType::F64(_)) => {
+                let current = match first {
+                    Some(Value::F64(v)) => *v,
+                    _ => 0.0,
+                };
+                serde_json::Value::from(current + amount.as_f64().ok_or_else(bad_amount)?)
+            }
+            _ => return Err(Error::QueryError(format!("'{}' is not a u64, i64 or f64 field", field))),
+        };
+        Ok(value)
+    }
+
+    /// Replaces or merges the document with the given id, returning true if a new document was created.
+    /// The document is read, deleted and added again while holding the writer exclusively, so no other
+    /// write or commit can land in between. A partial update is built from the stored fields of the
+    /// latest version of the document, committing it first if it was written since the last commit,
+    /// so it can only be used when every field is stored.
+    pub fn update_document(&self, id: &str, update: UpdateDocument) -> Result<bool> {
+        let schema = self.index.schema();
+        let (key, key_value) = self.key_value(id)?;
+        let term = self.key_term(id)?;
+        if let UpdateMode::Partial = update.mode {
+            self.require_stored("a partial update")?;
+        }
+        let mut index_writer = self.writer.write();
+        if self.pending_keys.lock().touches(&term) {
+            self.commit_locked(&mut index_writer)?;
+        }
+        let existing = self.get_document(id)?;
+        let created = existing.is_none();
+
+        let mut doc = match (update.mode, existing) {
+            (UpdateMode::Upsert, _) => LocalIndex::parse_doc(&schema, &update.document.to_string())?,
+            (UpdateMode::Partial, Some(existing)) => {
+                let fields = update
+                    .document
+                    .as_object()
+                    .ok_or_else(|| DocParsingError::NotJSON(update.document.to_string()))?;
+                let mut merged = serde_json::to_value(existing)?;
+                if let Some(merged) = merged.as_object_mut() {
+                    merged.extend(fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+                LocalIndex::parse_doc(&schema, &merged.to_string())?
+            }
+            (UpdateMode::Partial, None) => return Err(Error::UnknownDocument(id.into())),
+        };
+
+        match doc.get_first(key) {
+            Some(v) if *v == key_value => (),
+            Some(v) => {
+                let doc_key = LocalIndex::value_string(v);
+                return Err(Error::InvalidPrimaryKey(format!(
+                    "document key '{}' does not match id '{}'",
+                    doc_key, id
+                )));
+            }
+            None => doc.add(FieldValue::new(key, key_value)),
+        }
+
+        index_writer.delete_term(term.clone());
+        index_writer.add_document(doc);
+        self.pending_keys.lock().add(term);
+        drop(index_writer);
+        self.commit_if_requested(&update.options)?;
+        Ok(created)
+    }
+
+    /// Documents rebuilt from their stored fields lose every field that isn't stored, so `operation`,
+    /// which rebuilds them, can only be done when the whole schema is stored.
+    fn require_stored(&self, operation: &str) -> Result<()> {
+        match self.index.schema().fields().iter().find(|f| !f.is_stored()) {
+            Some(field) => Err(Error::QueryError(format!(
+                "{} rebuilds documents from their stored fields, which would lose the unstored field '{}' of {}",
+                operation,
+                field.name(),
+                self.name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The reader is reloaded as part of the commit rather than left to the commit watcher, so a
+    /// read issued right after a committed write will see it.
+    fn commit_if_requested(&self, options: &Option<IndexOptions>) -> Result<()> {
+        match options {
+            Some(opts) if opts.commit => {
+                self.commit()?;
+            }
+            _ => self.set_opstamp(self.get_opstamp() + 1),
+        }
+        Ok(())
+    }
+
+    fn highlighters(searcher: &Searcher, query: &dyn TantivyQuery, highlight: &Highlight) -> Result<Vec<(String, SnippetGenerator)>> {
+        let schema = searcher.schema();
+        highlight
+            .fields
+            .keys()
+            .map(|name| {
+                let field = schema.get_field(name).ok_or_else(|| Error::UnknownIndexField(name.clone()))?;
+                match schema.get_field_entry(field).field_type() {
+                    FieldType::Str(_) => (),
+                    _ => return Err(Error::QueryError(format!("Field {} must be a text field to highlight", name))),
+                }
+                let mut generator = SnippetGenerator::create(searcher, query, field)?;
+                generator.set_max_num_chars(highlight.fragment_size(name));
+                Ok((name.clone(), generator))
+            })
+            .collect()
+    }
+
+    /// The stored fields a search asked for, or `None` when the whole document should be returned.
+    fn projection(schema: &Schema, search: &Search) -> Result<Option<HashSet<Field>>> {
+        if search.fields.is_empty() && search.source.is_empty() {
+            return Ok(None);
+        }
+        let field = |name: &String| schema.get_field(name).ok_or_else(|| Error::UnknownIndexField(name.clone()));
+        let mut selected: HashSet<Field> = if search.fields.is_empty() && search.source.includes.is_empty() {
+            schema.fields().iter().enumerate().map(|(i, _)| Field(i as u32)).collect()
+        } else {
+            search.included_fields().map(field).collect::<Result<_>>()?
+        };
+        for excluded in &search.source.excludes {
+            selected.remove(&field(excluded)?);
+        }
+        Ok(Some(selected))
+    }
+
+    fn project(schema: &Schema, doc: &Document, projection: &Option<HashSet<Field>>) -> NamedDoc {
+        match projection {
+            None => schema.to_named_doc(doc).0,
+            Some(fields) => {
+                let mut named = NamedDoc::new();
+                for fv in doc.field_values().iter().filter(|fv| fields.contains(&fv.field())) {
+                    let name = schema.get_field_name(fv.field()).to_string();
+                    named.entry(name).or_default().push(fv.value().clone());
+                }
+                named
+            }
+        }
+    }
+
+    /// Sorting reads values straight from fast fields. tantivy doesn't support dates as fast fields, so
+    /// stored date fields are read from the doc store, which is much slower than sorting on a timestamp
+    /// indexed as an i64 fast field.
+    fn sort_fields(schema: &Schema, sort: &[SortField]) -> Result<Vec<(Field, Order)>> {
+        sort.iter()
+            .map(|s| {
+                let field = schema
+                    .get_field(&s.field)
+                    .ok_or_else(|| Error::UnknownIndexField(s.field.clone()))?;
+                let entry = schema.get_field_entry(field);
+                let sortable = match entry.field_type() {
+                    FieldType::U64(opts) | FieldType::I64(opts) | FieldType::F64(opts) => {
+                        opts.get_fastfield_cardinality() == Some(Cardinality::SingleValue)
+                    }
+                    FieldType::Date(_) => entry.is_stored(),
+                    _ => false,
+                };
+                if !sortable {
+                    return Err(Error::QueryError(format!(
+                        "Field {} must be a single valued u64, i64 or f64 fast field or a stored date field to sort by",
+                        s.field
+                    )));
+                }
+                Ok((field, s.order))
+            })
+            .collect()
+    }
+
+    /// Resolves each field of a facet query, duplicate paths are dropped so nothing is counted twice.
+    fn facet_fields(schema: &Schema, facets: &Option<FacetQuery>) -> Result<Vec<(String, Field, Vec<String>)>> {
+        facets
+            .iter()
+            .flat_map(FacetQuery::fields)
+            .map(|(name, paths, _)| {
+                let field = schema.get_field(name).ok_or_else(|| Error::UnknownIndexField(name.into()))?;
+                if schema.get_field_entry(field).field_type() != &FieldType::HierarchicalFacet {
+                    return Err(Error::QueryError(format!("Field {} is not a facet field", name)));
+                }
+                let mut paths = paths.to_vec();
+                paths.sort();
+                paths.dedup();
+                Ok((name.into(), field, paths))
+            })
+            .collect()
+    }
+
+    /// Terms can be counted over numeric fast fields or raw tokenized text fields, metrics only over
+    /// numeric fast fields. Bucket aggregations can also use stored date fields, read from the doc
+    /// store since tantivy doesn't support dates as fast fields, and can only nest metrics.
+    fn aggregations(schema: &Schema, aggs: &BTreeMap<String, Aggregation>) -> Result<Aggregations> {
+        aggs.iter().try_fold(Aggregations::new(), |collector, (name, agg)| {
+            let (field, numeric, text, date) = LocalIndex::aggregation_field(schema, agg.field())?;
+            let bucket_metrics = || LocalIndex::bucket_metrics(schema, name, agg);
+            let requirement = match agg {
+                Aggregation::Terms { size, .. } if numeric || text => {
+                    return Ok(collector.terms(name, field, Aggregation::shard_size(*size)))
+                }
+                Aggregation::Metrics { .. } if numeric => return Ok(collector.metrics(name, field)),
+                Aggregation::Histogram { interval, .. } if (numeric || date) && interval.is_finite() && *interval > 0.0 => {
+                    return Ok(collector.buckets(name, field, Buckets::Histogram(*interval), bucket_metrics()?));
+                }
+                Aggregation::DateHistogram { interval, .. } if numeric || date => {
+                    return Ok(collector.buckets(name, field, Buckets::DateHistogram(*interval), bucket_metrics()?));
+                }
+                Aggregation::Range { ranges, .. } if numeric || date => {
+                    return Ok(collector.buckets(name, field, Buckets::Range(ranges.clone()), bucket_metrics()?));
+                }
+                Aggregation::Terms { .. } => "must be a u64, i64 or f64 fast field or a raw tokenized text field",
+                Aggregation::Metrics { .. } => "must be a u64, i64 or f64 fast field",
+                Aggregation::Histogram { .. } if numeric || date => "needs an interval greater than zero",
+                _ => "must be a u64, i64 or f64 fast field or a stored date field",
+            };
+            Err(Error::QueryError(format!(
+                "Field {} {} for aggregation {}",
+                agg.field(),
+                requirement,
+                name
+            )))
+        })
+    }
+
+    /// Looks up an aggregation's field along with whether it's a numeric fast field, a raw tokenized
+    /// text field or a stored date field. Counting the terms of a tokenized text field means walking
+    /// the postings of every word in the index on each search, so those aren't allowed.
+    fn aggregation_field(schema: &Schema, name: &str) -> Result<(Field, bool, bool, bool)> {
+        let field = schema.get_field(name).ok_or_else(|| Error::UnknownIndexField(name.to_string()))?;
+        let entry = schema.get_field_entry(field);
+        let (numeric, text, date) = match entry.field_type() {
+            FieldType::U64(opts) | FieldType::I64(opts) | FieldType::F64(opts) => (opts.is_fast(), false, false),
+            FieldType::Str(opts) => (
+                false,
+                opts.get_indexing_options().filter(|o| o.tokenizer() == "raw").is_some(),
+                false,
+            ),
+            FieldType::Date(_) => (false, false, entry.is_stored()),
+            _ => (false, false, false),
+        };
+        Ok((field, numeric, text, date))
+    }
+
+    fn bucket_metrics(schema: &Schema, name: &str, agg: &Aggregation) -> Result<Vec<(String, Field)>> {
+        agg.sub_aggs()
+            .into_iter()
+            .flatten()
+            .map(|(sub_name, sub)| match sub {
+                Aggregation::Metrics { field } => match LocalIndex::aggregation_field(schema, field)? {
+                    (field, true, _, _) => Ok((sub_name.clone(), field)),
+                    _ => Err(Error::QueryError(format!(
+                        "Field {} must be a u64, i64 or f64 fast field for aggregation {}",
+                        field, sub_name
+                    ))),
+                },
+                _ => Err(Error::QueryError(format!("Only metrics aggregations can be nested in {}", name))),
+            })
+            .collect()
+    }
+
     fn parse_doc(schema: &Schema, bytes: &str) -> Result<Document> {
-        schema.parse_document(bytes).map_err(Into::into)
+        mapping::parse_document(schema, bytes)
     }
 
     pub fn get_space(&self) -> SearcherSpaceUsage {
@@ -236,10 +1024,35 @@ impl LocalIndex {
         &self.index
     }
 
+    pub fn get_reader(&self) -> &IndexReader {
+        &self.reader
+    }
+
+    pub fn deleted_docs(&self) -> u64 {
+        self.deleted_docs.load(Ordering::SeqCst)
+    }
+
     pub fn recreate_writer(self) -> Result<Self> {
         LocalIndex::new(self.index, self.settings.clone(), &self.name)
     }
 
+    /// Stops this index's writer, waiting on any merges still in flight. If another clone of this
+    /// handle is still alive the writer is left running and is stopped once that clone is dropped.
+    pub fn shutdown(self) -> Result<()> {
+        match Arc::try_unwrap(self.writer) {
+            Ok(writer) => writer.into_inner().wait_merging_threads().map_err(Into::into),
+            Err(_) => {
+                debug!("Writer for {} is still in use, it will be released when dropped", self.name);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether another clone of this handle, such as one held by a request in flight, shares its writer.
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.writer) > 1
+    }
+
     pub fn get_writer(&self) -> Arc<RwLock<IndexWriter>> {
         Arc::clone(&self.writer)
     }
@@ -5,17 +5,23 @@ use std::time::Instant;
 
 use bytes::Bytes;
 use crossbeam::channel::{unbounded, Receiver, Sender};
+use futures::sync::oneshot;
 use http::StatusCode;
 use hyper::Body;
 use parking_lot::RwLock;
 use tantivy::schema::Schema;
-use tantivy::{Document, IndexWriter};
+use tantivy::Document;
 use tokio::prelude::*;
 use tracing::*;
 
+use toshi_types::error::Error;
+use toshi_types::server::BulkResult;
+
+use crate::handle::LocalIndex;
 use crate::handlers::ResponseFuture;
 use crate::index::IndexCatalog;
-use crate::utils::empty_with_code;
+use crate::mapping::parse_document;
+use crate::utils::{empty_with_code, error_response, with_body};
 
 #[derive(Clone)]
 pub struct BulkHandler {
@@ -28,50 +34,92 @@ impl BulkHandler {
         BulkHandler { catalog, watcher }
     }
 
+    /// Documents whose primary key is already in the index are skipped, like lines that don't parse,
+    /// and the reason for each is sent back with the count of documents indexed.
     fn index_documents(
-        index_writer: Arc<RwLock<IndexWriter>>,
-        doc_receiver: Receiver<Document>,
+        index: LocalIndex,
+        doc_receiver: Receiver<Result<Document, Error>>,
         watcher: Arc<AtomicBool>,
+        report: oneshot::Sender<BulkResult>,
     ) -> impl Future<Item = (), Error = ()> {
         future::lazy(move || {
             let start = Instant::now();
+            let mut result = BulkResult::default();
             for doc in doc_receiver {
-                let w = index_writer.read();
-                w.add_document(doc);
+                match doc.and_then(|doc| index.add_unique(doc)) {
+                    Ok(()) => result.docs_affected += 1,
+                    Err(e) => {
+                        warn!("Skipped a document: {}", e);
+                        result.skipped.push(e.to_string());
+                    }
+                }
             }
 
             info!("Piping Documents took: {:?}", start.elapsed());
             info!("Unlocking watcher...");
             watcher.store(false, Ordering::SeqCst);
+            let _ = report.send(result);
             Ok(())
         })
     }
 
-    fn parsing_documents(schema: Schema, doc_sender: Sender<Document>, line_recv: Receiver<Bytes>) -> impl Future<Item = (), Error = ()> {
+    fn parsing_documents(
+        schema: Schema,
+        doc_sender: Sender<Result<Document, Error>>,
+        line_recv: Receiver<Bytes>,
+    ) -> impl Future<Item = (), Error = ()> {
         future::lazy(move || {
             for line in line_recv {
-                if !line.is_empty() {
-                    if let Ok(text) = from_utf8(&line) {
-                        if let Ok(doc) = schema.parse_document(text) {
-                            debug!("Sending doc: {:?}", &doc);
-                            doc_sender.send(doc).unwrap()
-                        }
-                    }
+                if !line.iter().all(u8::is_ascii_whitespace) {
+                    let doc = from_utf8(&line)
+                        .map_err(|e| Error::QueryError(format!("line is not valid UTF-8: {}", e)))
+                        .and_then(|text| parse_document(&schema, text));
+                    debug!("Sending doc: {:?}", &doc);
+                    doc_sender.send(doc).unwrap()
                 }
             }
             Ok(())
         })
     }
 
+    /// Indexes newline delimited documents. If the index doesn't exist it's created from the first
+    /// document, which means reading the whole body before indexing any of it.
     pub fn bulk_insert(&self, body: Body, index: String) -> ResponseFuture {
-        self.watcher.store(true, Ordering::SeqCst);
+        let index = match self.catalog.read().resolve_write_index(&index) {
+            Ok(index) => index,
+            Err(e) => return Box::new(future::ok(error_response(StatusCode::BAD_REQUEST, e))),
+        };
+        if self.catalog.read().exists(&index) {
+            return self.insert_lines(body, index);
+        }
+        let handler = self.clone();
+        let fut = body.concat2().and_then(move |b| {
+            let first = b.split(|c| *c == b'\n').find(|l| !l.iter().all(u8::is_ascii_whitespace));
+            let created = match first.map(serde_json::from_slice::<serde_json::Value>) {
+                Some(Ok(doc)) => handler.catalog.write().create_dynamic(&index, &doc),
+                Some(Err(e)) => Err(Error::from(e)),
+                None => Err(Error::UnknownIndex(index.clone())),
+            };
+            match created {
+                Ok(()) => future::Either::A(handler.insert_lines(Body::from(b.into_bytes()), index)),
+                Err(e @ Error::UnknownIndex(_)) => future::Either::B(future::ok(error_response(StatusCode::NOT_FOUND, e))),
+                Err(e) => future::Either::B(future::ok(error_response(StatusCode::BAD_REQUEST, e))),
+            }
+        });
+        Box::new(fut)
+    }
+
+    fn insert_lines(&self, body: Body, index: String) -> ResponseFuture {
         let index_lock = self.catalog.read();
-        let index_handle = index_lock.get_index(&index).unwrap();
-        let index = index_handle.get_index();
-        let schema = index.schema();
+        let index_handle = match index_lock.get_index(&index) {
+            Ok(handle) => handle,
+            Err(e) => return Box::new(future::ok(error_response(StatusCode::NOT_FOUND, e))),
+        };
+        self.watcher.store(true, Ordering::SeqCst);
+        let schema = index_handle.get_index().schema();
         let (line_sender, line_recv) = index_lock.settings.get_channel::<Bytes>();
-        let (doc_sender, doc_recv) = unbounded::<Document>();
-        let writer = index_handle.get_writer();
+        let (doc_sender, doc_recv) = unbounded::<Result<Document, Error>>();
+        let handle = index_handle.clone();
         let num_threads = index_lock.settings.json_parsing_threads;
         let line_sender_clone = line_sender.clone();
 
@@ -101,9 +149,17 @@ impl BulkHandler {
                 if !left.is_empty() {
                     line_sender.send(left).expect("Line sender failed #2");
                 }
-                tokio::spawn(BulkHandler::index_documents(writer, doc_recv, watcher_clone));
-
-                future::ok(empty_with_code(StatusCode::CREATED))
+                let (report, result) = oneshot::channel();
+                tokio::spawn(BulkHandler::index_documents(handle, doc_recv, watcher_clone, report));
+
+                result.then(|result| match result {
+                    Ok(result) => {
+                        let mut resp = with_body(result);
+                        *resp.status_mut() = StatusCode::CREATED;
+                        Ok(resp)
+                    }
+                    Err(_) => Ok(empty_with_code(StatusCode::INTERNAL_SERVER_ERROR)),
+                })
             });
 
         Box::new(fut)
@@ -138,10 +194,12 @@ mod tests {
 
         let index_docs = handler.bulk_insert(Body::from(body), "test_index".into());
         let result = runtime.block_on(index_docs);
+        let report: BulkResult = serde_json::from_slice(&runtime.block_on(result.unwrap().into_body().concat2())?)?;
+        assert_eq!(report.docs_affected, 3);
+        assert!(report.skipped.is_empty());
 
         let flush = flush(Arc::clone(&server), "test_index".to_string());
         runtime.block_on(flush)?;
-        assert_eq!(result.is_ok(), true);
         sleep(Duration::from_secs(3));
 
         let search = SearchHandler::new(Arc::clone(&server));
@@ -151,4 +209,39 @@ mod tests {
         assert_eq!(docs.hits, 8);
         Ok(())
     }
+
+    #[test]
+    fn test_bulk_reports_skipped() -> Result<(), Box<dyn std::error::Error>> {
+        let mut runtime = Builder::new().core_threads(1).blocking_threads(4).build()?;
+        let server = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = BulkHandler::new(Arc::clone(&server), Arc::new(AtomicBool::new(false)));
+
+        let body = r#"
+        {"test_text": "new", "test_u64": 321}
+        {"test_text": "same key again", "test_u64": 321}
+        {"test_text": "already indexed", "test_u64": 10}
+        {"test_text": "not json"#;
+
+        let resp = runtime.block_on(handler.bulk_insert(Body::from(body), "test_index".into()))?;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let report: BulkResult = serde_json::from_slice(&runtime.block_on(resp.into_body().concat2())?)?;
+        assert_eq!(report.docs_affected, 1);
+        assert_eq!(report.skipped.len(), 3);
+        assert!(report.skipped.contains(&Error::DuplicateKey("321".into()).to_string()));
+        assert!(report.skipped.contains(&Error::DuplicateKey("10".into()).to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_missing_index() -> Result<(), Box<dyn std::error::Error>> {
+        let mut runtime = Builder::new().core_threads(1).blocking_threads(4).build()?;
+        let server = create_test_catalog("test_index");
+        let handler = BulkHandler::new(Arc::clone(&server), Arc::new(AtomicBool::new(false)));
+
+        let body = r#"{"test_text": "nowhere to go"}"#;
+        let resp = runtime.block_on(handler.bulk_insert(Body::from(body), "missing_index".into()))?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(!server.read().exists("missing_index"));
+        Ok(())
+    }
 }
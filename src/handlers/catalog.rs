@@ -0,0 +1,309 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use futures::future;
+use http::header::CONTENT_TYPE;
+use http::{Response, StatusCode};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use tokio::prelude::*;
+
+use crate::cluster::remote_handle::RemoteIndex;
+use crate::handle::{IndexHandle, IndexLocation, LocalIndex};
+use crate::handlers::ResponseFuture;
+use crate::index::SharedCatalog;
+use crate::utils::{empty_with_code, error_response, with_body};
+use toshi_types::error::Error;
+use toshi_types::server::{AliasActions, IndexTemplate};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexEntry {
+    pub name: String,
+    pub location: IndexLocation,
+    pub docs: u64,
+    pub segments: usize,
+    pub deleted_docs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+}
+
+impl IndexEntry {
+    fn from_local(index: &LocalIndex) -> Self {
+        let searcher = index.get_reader().searcher();
+        Self {
+            name: index.get_name(),
+            location: index.index_location(),
+            docs: searcher.num_docs(),
+            segments: searcher.segment_readers().len(),
+            deleted_docs: index.deleted_docs(),
+            size: Some(searcher.space_usage().total()),
+        }
+    }
+
+    fn from_remote(index: &RemoteIndex, metas: Vec<RemoteMeta>) -> Self {
+        let segments = metas.iter().flat_map(|m| m.segments.iter());
+        let (docs, deleted, count) = segments.fold((0u64, 0u64, 0usize), |(docs, deleted, count), seg| {
+            let seg_deleted = seg.deletes.as_ref().map(|d| u64::from(d.num_deleted_docs)).unwrap_or(0);
+            (docs + u64::from(seg.max_doc) - seg_deleted, deleted + seg_deleted, count + 1)
+        });
+        Self {
+            name: index.get_name(),
+            location: index.index_location(),
+            docs,
+            segments: count,
+            deleted_docs: deleted,
+            size: None,
+        }
+    }
+}
+
+/// The subset of tantivy's `meta.json` needed to describe an index living on another node.
+#[derive(Deserialize)]
+struct RemoteMeta {
+    segments: Vec<RemoteSegment>,
+}
+
+#[derive(Deserialize)]
+struct RemoteSegment {
+    max_doc: u32,
+    deletes: Option<RemoteDeletes>,
+}
+
+#[derive(Deserialize)]
+struct RemoteDeletes {
+    num_deleted_docs: u32,
+}
+
+fn collect_entries(catalog: SharedCatalog) -> impl Future<Item = Vec<IndexEntry>, Error = hyper::Error> + Send {
+    let cat = catalog.read();
+    let local: Vec<IndexEntry> = cat.get_collection().values().map(IndexEntry::from_local).collect();
+    let remotes: Vec<RemoteIndex> = cat.get_remote_collection().lock().values().cloned().collect();
+
+    let remote_entries = remotes.into_iter().map(|remote| {
+        remote.summary().then(move |res| {
+            let metas = res
+                .map(|replies| {
+                    replies
+                        .iter()
+                        .filter_map(|r| serde_json::from_slice::<RemoteMeta>(&r.summary).ok())
+                        .collect()
+                })
+                .unwrap_or_else(|e| {
+                    tracing::error!("Unable to fetch summary for {}: {:?}", remote.get_name(), e);
+                    Vec::new()
+                });
+            Ok(IndexEntry::from_remote(&remote, metas))
+        })
+    });
+
+    future::join_all(remote_entries).map(move |remote| {
+        let mut entries = local;
+        entries.extend(remote);
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    })
+}
+
+pub fn list_indexes(catalog: SharedCatalog) -> ResponseFuture {
+    let fut = collect_entries(Arc::clone(&catalog)).map(with_body);
+    Box::new(fut)
+}
+
+pub fn cat_indexes(catalog: SharedCatalog) -> ResponseFuture {
+    let fut = collect_entries(Arc::clone(&catalog)).map(|entries| {
+        let mut table = format!(
+            "{:<30} {:<8} {:>12} {:>10} {:>14} {:>14}\n",
+            "name", "location", "docs", "segments", "deleted", "size"
+        );
+        for e in entries {
+            let size = e.size.map(|s| s.to_string()).unwrap_or_else(|| "-".into());
+            let location = match e.location {
+                IndexLocation::LOCAL => "local",
+                IndexLocation::REMOTE => "remote",
+            };
+            writeln!(
+                table,
+                "{:<30} {:<8} {:>12} {:>10} {:>14} {:>14}",
+                e.name, location, e.docs, e.segments, e.deleted_docs, size
+            )
+            .unwrap();
+        }
+        Response::builder()
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from(table))
+            .unwrap()
+    });
+    Box::new(fut)
+}
+
+pub fn list_aliases(catalog: SharedCatalog) -> ResponseFuture {
+    let aliases = with_body(catalog.read().aliases().all());
+    Box::new(future::ok(aliases))
+}
+
+/// Applies a list of alias actions in one step, so an alias can be moved from one index to another
+/// without a moment where it points at neither or both. Responds with every alias afterwards.
+pub fn update_aliases(catalog: SharedCatalog, body: Body) -> ResponseFuture {
+    let fut = body.concat2().map(move |b| {
+        let req = match serde_json::from_slice::<AliasActions>(&b) {
+            Ok(v) => v,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+        };
+        let mut cat = catalog.write();
+        match cat.update_aliases(&req.actions) {
+            Ok(()) => with_body(cat.aliases().all()),
+            Err(e @ Error::UnknownIndex(_)) => error_response(StatusCode::NOT_FOUND, e),
+            Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+        }
+    });
+    Box::new(fut)
+}
+
+pub fn list_templates(catalog: SharedCatalog) -> ResponseFuture {
+    let templates = with_body(catalog.read().templates().all());
+    Box::new(future::ok(templates))
+}
+
+pub fn get_template(catalog: SharedCatalog, name: String) -> ResponseFuture {
+    let resp = match catalog.read().templates().get(&name) {
+        Some(template) => with_body(template),
+        None => empty_with_code(StatusCode::NOT_FOUND),
+    };
+    Box::new(future::ok(resp))
+}
+
+/// Adds or replaces a template, it's used by indexes created from then on.
+pub fn put_template(catalog: SharedCatalog, body: Body, name: String) -> ResponseFuture {
+    let fut = body.concat2().map(move |b| {
+        let template = match serde_json::from_slice::<IndexTemplate>(&b) {
+            Ok(v) => v,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+        };
+        match catalog.write().put_template(&name, template) {
+            Ok(()) => empty_with_code(StatusCode::CREATED),
+            Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+        }
+    });
+    Box::new(fut)
+}
+
+pub fn delete_template(catalog: SharedCatalog, name: String) -> ResponseFuture {
+    let resp = match catalog.write().remove_template(&name) {
+        Ok(true) => empty_with_code(StatusCode::OK),
+        Ok(false) => empty_with_code(StatusCode::NOT_FOUND),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    Box::new(future::ok(resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use std::collections::BTreeMap;
+
+    use toshi_types::client::CountResult;
+    use toshi_types::server::{Alias, DocsAffected, IndexSettings};
+
+    use crate::handlers::search::tests::wait_json;
+    use crate::handlers::{IndexHandler, SearchHandler};
+    use crate::index::tests::{create_test_catalog, create_test_catalog_with_key};
+    use crate::SearchResults;
+
+    use super::*;
+
+    #[test]
+    fn test_list_indexes() {
+        let catalog = create_test_catalog("test_index");
+        let entries: Vec<IndexEntry> = wait_json(list_indexes(catalog).wait().unwrap());
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "test_index");
+        assert_eq!(entries[0].location, IndexLocation::LOCAL);
+        assert_eq!(entries[0].docs, 5);
+        assert_eq!(entries[0].segments, 1);
+        assert_eq!(entries[0].deleted_docs, 0);
+        assert_eq!(entries[0].size.is_some(), true);
+    }
+
+    #[test]
+    fn test_alias_cutover() {
+        let catalog = create_test_catalog("products-1");
+        catalog
+            .write()
+            .add_index("products-2".into(), toshi_test::create_test_index())
+            .unwrap();
+        let search = SearchHandler::new(Arc::clone(&catalog));
+        let hits_in = |name: &str| -> Vec<String> {
+            let results: SearchResults = wait_json(search.all_docs(name.into()).wait().unwrap());
+            let mut indexes: Vec<String> = results.docs.into_iter().filter_map(|d| d.index).collect();
+            indexes.dedup();
+            indexes
+        };
+
+        let body = r#"{ "actions": [{ "add": { "index": "products-1", "alias": "products" } }] }"#;
+        let resp = update_aliases(Arc::clone(&catalog), Body::from(body)).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hits_in("products"), vec!["products-1"]);
+
+        let swap = r#"{ "actions": [
+            { "remove": { "index": "products-1", "alias": "products" } },
+            { "add": { "index": "products-2", "alias": "products" } }
+        ] }"#;
+        update_aliases(Arc::clone(&catalog), Body::from(swap)).wait().unwrap();
+        assert_eq!(hits_in("products"), vec!["products-2"]);
+        assert_eq!(catalog.read().resolve_write_index("products").unwrap(), "products-2");
+
+        let bad = r#"{ "actions": [{ "add": { "index": "products-3", "alias": "products" } }] }"#;
+        let resp = update_aliases(Arc::clone(&catalog), Body::from(bad)).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let aliases: BTreeMap<String, Alias> = wait_json(list_aliases(Arc::clone(&catalog)).wait().unwrap());
+        assert_eq!(aliases["products"].write_index.as_deref(), Some("products-2"));
+    }
+
+    #[test]
+    fn test_alias_of_several_indexes() {
+        let catalog = create_test_catalog_with_key("logs-1", "test_u64");
+        let keyed = toshi_test::create_test_index();
+        LocalIndex::write_settings(&keyed, &IndexSettings::with_primary_key("test_u64")).unwrap();
+        catalog.write().add_index("logs-2".into(), keyed).unwrap();
+        let add = r#"{ "actions": [
+            { "add": { "index": "logs-1", "alias": "logs" } },
+            { "add": { "index": "logs-2", "alias": "logs", "is_write_index": true } }
+        ] }"#;
+        update_aliases(Arc::clone(&catalog), Body::from(add)).wait().unwrap();
+        let search = SearchHandler::new(Arc::clone(&catalog));
+        let handler = IndexHandler::new(Arc::clone(&catalog));
+        let count = |name: &str| -> usize {
+            let resp = search.count(Body::from("{}"), name.into()).wait().unwrap();
+            wait_json::<CountResult>(resp).count
+        };
+        assert_eq!(count("logs"), 10);
+
+        let doc = r#"{ "options": { "commit": true }, "document": { "test_text": "Written through", "test_u64": 99 } }"#;
+        let resp = handler.add_document(Body::from(doc), "logs".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!((count("logs-1"), count("logs-2")), (5, 6));
+
+        let delete = r#"{ "query": { "range": { "test_i64": { "gte": 2016 } } } }"#;
+        let resp = handler.delete_by_query(Body::from(delete), "logs".into()).wait().unwrap();
+        assert_eq!(wait_json::<DocsAffected>(resp).docs_affected, 4);
+        assert_eq!((count("logs-1"), count("logs-2")), (3, 4));
+
+        let resp = handler.multi_get(Body::from(r#"{ "ids": ["1"] }"#), "logs".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_cat_indexes() {
+        let catalog = create_test_catalog("test_index");
+        let body = cat_indexes(catalog).wait().unwrap().into_body().concat2().wait().unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].starts_with("name"), true);
+        assert_eq!(lines[1].starts_with("test_index"), true);
+        assert_eq!(lines[1].contains("local"), true);
+    }
+}
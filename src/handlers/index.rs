@@ -7,20 +7,24 @@ use http::{Response, StatusCode};
 use hyper::Body;
 use rand::random;
 use tantivy::schema::*;
-use tantivy::Index;
 use tower_grpc::Request;
 
 use toshi_proto::cluster_rpc::PlaceRequest;
+use toshi_types::client::{FetchedDoc, MultiGetResults};
 use toshi_types::error::Error;
-use toshi_types::server::{DeleteDoc, DocsAffected, SchemaBody};
+use toshi_types::query::Query;
+use toshi_types::server::{
+    DeleteByQuery, DeleteDoc, DocsAffected, IndexSettings, MultiGet, Reindex, SchemaBody, SchemaChange, UpdateByQuery,
+};
 
 use crate::cluster::rpc_server::RpcClient;
 use crate::cluster::RPCError;
-use crate::handle::IndexHandle;
+use crate::handle::{IndexHandle, LocalIndex};
 use crate::handlers::ResponseFuture;
 use crate::index::{IndexCatalog, SharedCatalog};
+use crate::mapping::{append_fields, needs_reindex};
 use crate::utils::{empty_with_code, error_response, with_body};
-use crate::AddDocument;
+use crate::{AddDocument, UpdateDocument};
 
 #[derive(Clone)]
 pub struct IndexHandler {
@@ -32,11 +36,6 @@ impl IndexHandler {
         IndexHandler { catalog }
     }
 
-    #[inline]
-    fn add_index(catalog: SharedCatalog, name: String, index: Index) -> Result<(), Error> {
-        catalog.write().add_index(name, index)
-    }
-
     #[inline]
     fn add_remote_index(catalog: SharedCatalog, name: String, clients: Vec<RpcClient>) -> Result<(), Error> {
         catalog.write().add_multi_remote_index(name, clients)
@@ -44,21 +43,29 @@ impl IndexHandler {
 
     fn delete_terms(catalog: SharedCatalog, body: DeleteDoc, index: &str) -> Result<DocsAffected, Error> {
         let index_lock = catalog.read();
-        let index_handle = index_lock.get_index(index)?;
+        let index_handle = index_lock.get_index(&index_lock.resolve_write_index(index)?)?;
         index_handle.delete_term(body)
     }
 
-    fn create_remote_index(nodes: &[String], index: String, schema: Schema) -> impl Stream<Item = Vec<RpcClient>, Error = RPCError> + Send {
+    fn create_remote_index(
+        nodes: &[String],
+        index: String,
+        schema: Schema,
+        settings: IndexSettings,
+    ) -> impl Stream<Item = Vec<RpcClient>, Error = RPCError> + Send {
         let futs = nodes.iter().map(move |n| {
             let c = IndexCatalog::create_client(n.clone());
             let index = index.clone();
             let schema = schema.clone();
+            let settings = settings.clone();
             c.and_then(move |mut client| {
                 let client_clone = client.clone();
                 let schema_bytes = serde_json::to_vec(&schema).unwrap();
+                let settings_bytes = serde_json::to_vec(&settings).unwrap();
                 let request = Request::new(PlaceRequest {
                     index,
                     schema: schema_bytes,
+                    settings: settings_bytes,
                 });
                 client.place_index(request).map(move |_| vec![client_clone]).map_err(Into::into)
             })
@@ -87,31 +94,70 @@ impl IndexHandler {
         Box::new(fut)
     }
 
-    pub fn create_index(&self, body: Body, index: String) -> ResponseFuture {
+    /// Deletes the documents matching a query on every shard of every index the name resolves to, the
+    /// response counts the documents removed once the deletes are committed.
+    pub fn delete_by_query(&self, body: Body, index: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = body.concat2().and_then(move |b| {
+            let req = match serde_json::from_slice::<DeleteByQuery>(&b) {
+                Ok(v) => v,
+                Err(e) => return Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, Error::from(e)))),
+            };
+            let c = cat.read();
+            let indexes = c.resolve_indexes(&index);
+            if indexes.is_empty() {
+                return Either::A(future::ok(empty_with_code(StatusCode::NOT_FOUND)));
+            }
+            let mut tasks = Vec::new();
+            for index in &indexes {
+                if c.exists(index) {
+                    tasks.push(Either::A(c.delete_local_by_query(index, req.clone())));
+                }
+                if c.remote_exists(index) {
+                    tasks.push(Either::B(c.delete_remote_by_query(index, req.clone())));
+                }
+            }
+            Either::B(
+                future::join_all(tasks)
+                    .map(|counts| {
+                        with_body(DocsAffected {
+                            docs_affected: counts.iter().sum(),
+                        })
+                    })
+                    .or_else(|e| match e {
+                        Error::IOError(_) => future::ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, e)),
+                        _ => future::ok(error_response(StatusCode::BAD_REQUEST, e)),
+                    }),
+            )
+        });
+
+        Box::new(fut)
+    }
+
+    pub fn create_index(&self, body: Body, index: String, settings: IndexSettings) -> ResponseFuture {
         let cat = Arc::clone(&self.catalog);
         Box::new(body.concat2().and_then(move |b| {
             let b = match serde_json::from_slice::<SchemaBody>(&b) {
                 Ok(v) => v,
                 Err(e) => return future::Either::A(future::ok(Response::from(Error::from(e)))),
             };
+            if let Err(e) = settings.validate(&b.0) {
+                return future::Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, e)));
+            }
+            if cat.read().aliases().get(&index).is_some() {
+                let e = Error::QueryError(format!("index '{}' has the same name as an alias", index));
+                return future::Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, e)));
+            }
 
-            {
-                let base_path = cat.read().base_path().clone();
-                let new_index: Index = match IndexCatalog::create_from_managed(base_path, &index, b.0.clone()) {
-                    Ok(v) => v,
-                    Err(e) => return future::Either::A(future::ok(Response::from(e))),
-                };
-                match IndexHandler::add_index(Arc::clone(&cat), index.clone(), new_index) {
-                    Ok(_) => (),
-                    Err(e) => return future::Either::A(future::ok(Response::from(e))),
-                };
+            if let Err(e) = cat.write().create_index(&index, b.0.clone(), &settings) {
+                return future::Either::A(future::ok(Response::from(e)));
             }
 
             let expir = cat.read().settings.experimental;
             if expir {
                 let nodes = &cat.read().settings.get_nodes();
                 future::Either::B(
-                    IndexHandler::create_remote_index(&nodes, index.clone(), b.0)
+                    IndexHandler::create_remote_index(&nodes, index.clone(), b.0, settings)
                         .concat2()
                         .map(move |clients| {
                             IndexHandler::add_remote_index(cat, index, clients)
@@ -126,13 +172,292 @@ impl IndexHandler {
         }))
     }
 
+    pub fn get_schema(&self, index: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = future::lazy(move || match cat.read().get_index(&index) {
+            Ok(handle) => future::ok(with_body(SchemaBody(handle.get_index().schema()))),
+            Err(e) => future::ok(error_response(StatusCode::NOT_FOUND, e)),
+        });
+
+        Box::new(fut)
+    }
+
+    /// Appends fields to a local index's schema. Fields already in the schema with the same definition
+    /// are skipped, so repeating a request changes nothing.
+    pub fn add_fields(&self, body: Body, index: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let fields = match serde_json::from_slice::<SchemaBody>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            match IndexHandler::evolve_schema(&cat, &index, fields.0) {
+                Ok(change) => with_body(change),
+                Err(e @ Error::UnknownIndex(_)) => error_response(StatusCode::NOT_FOUND, e),
+                Err(e @ Error::IOError(_)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    /// Fields tantivy can't add to existing segments are handled with a rolling reindex: the documents
+    /// are copied into a new version of the index while searches keep using the old one, and only
+    /// writes to this index wait. If a write slips in between the copy and the swap, the copy is
+    /// redone with the catalog locked, see `IndexCatalog::swap_index`.
+    fn evolve_schema(catalog: &SharedCatalog, index: &str, fields: Schema) -> Result<SchemaChange, Error> {
+        let (base_path, handle) = {
+            let cat = catalog.read();
+            (cat.base_path().clone(), cat.get_owned_index(index)?)
+        };
+        let (name, current) = (handle.get_name(), handle.get_index().schema());
+        let mut added = Vec::new();
+        for entry in fields.fields() {
+            match current.get_field(entry.name()) {
+                Some(field) if current.get_field_entry(field) == entry => (),
+                Some(_) => {
+                    return Err(Error::QueryError(format!(
+                        "field '{}' already exists, fields can't be changed",
+                        entry.name()
+                    )))
+                }
+                None => added.push(entry.clone()),
+            }
+        }
+        let mut settings = handle.index_settings().clone();
+        if added.is_empty() {
+            return Ok(SchemaChange {
+                schema_version: settings.schema_version,
+                reindexed: false,
+            });
+        }
+        settings.schema_version += 1;
+        let schema = append_fields(&current, &added);
+        let reindexed = added.iter().any(needs_reindex);
+        if !reindexed {
+            drop(handle);
+            catalog.write().extend_in_place(&name, schema, &settings)?;
+        } else {
+            if let Some(field) = current.fields().iter().find(|f| !f.is_stored()) {
+                return Err(Error::QueryError(format!(
+                    "adding these fields means reindexing '{}', which would lose the unstored field '{}'",
+                    name,
+                    field.name()
+                )));
+            }
+            let staging = IndexCatalog::staging_name(&name, settings.schema_version);
+            let copied = IndexCatalog::stage_index(&base_path, &staging, schema.clone(), &settings, &handle)?;
+            drop(handle);
+            catalog.write().swap_index(&name, &staging, schema, &settings, &copied)?;
+        }
+        Ok(SchemaChange {
+            schema_version: settings.schema_version,
+            reindexed,
+        })
+    }
+
+    pub fn get_document(&self, index: String, id: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = future::lazy(move || {
+            let cat = cat.read();
+            let doc = cat
+                .resolve_write_index(&index)
+                .and_then(|index| cat.get_index(&index)?.get_document(&id));
+            match doc {
+                Ok(Some(doc)) => future::ok(with_body(FetchedDoc::new(id, Some(doc)))),
+                Ok(None) => future::ok(error_response(StatusCode::NOT_FOUND, Error::UnknownDocument(id))),
+                Err(e @ Error::UnknownIndex(_)) => future::ok(error_response(StatusCode::NOT_FOUND, e)),
+                Err(e) => future::ok(error_response(StatusCode::BAD_REQUEST, e)),
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    pub fn multi_get(&self, body: Body, index: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let req = match serde_json::from_slice::<MultiGet>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            let cat = cat.read();
+            let handle = match cat.get_index(&index) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::NOT_FOUND, e),
+            };
+            let docs: Result<Vec<_>, Error> = req
+                .ids
+                .into_iter()
+                .map(|id| handle.get_document(&id).map(|doc| FetchedDoc::new(id, doc)))
+                .collect();
+            match docs {
+                Ok(docs) => with_body(MultiGetResults { docs }),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    pub fn update_document(&self, body: Body, index: String, id: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let update = match serde_json::from_slice::<UpdateDocument>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            let cat = cat.read();
+            let updated = cat
+                .resolve_write_index(&index)
+                .and_then(|index| cat.get_index(&index)?.update_document(&id, update));
+            match updated {
+                Ok(true) => empty_with_code(StatusCode::CREATED),
+                Ok(false) => empty_with_code(StatusCode::OK),
+                Err(e @ Error::UnknownIndex(_)) | Err(e @ Error::UnknownDocument(_)) => error_response(StatusCode::NOT_FOUND, e),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    /// Applies field updates to every document matching a query in each local index the name resolves
+    /// to, the response counts the documents changed.
+    pub fn update_by_query(&self, body: Body, index: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let req = match serde_json::from_slice::<UpdateByQuery>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            let handles: Vec<_> = {
+                let cat = cat.read();
+                cat.resolve_indexes(&index)
+                    .iter()
+                    .filter_map(|i| cat.get_owned_index(i).ok())
+                    .collect()
+            };
+            let updated = if handles.is_empty() {
+                Err(Error::UnknownIndex(index))
+            } else {
+                handles.iter().try_fold(DocsAffected { docs_affected: 0 }, |total, handle| {
+                    let affected = handle.update_by_query(req.query.clone(), &req.updates)?;
+                    Ok(DocsAffected {
+                        docs_affected: total.docs_affected + affected.docs_affected,
+                    })
+                })
+            };
+            match updated {
+                Ok(affected) => with_body(affected),
+                Err(e @ Error::UnknownIndex(_)) => error_response(StatusCode::NOT_FOUND, e),
+                Err(e @ Error::IOError(_)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    /// Copies the documents of one local index into another, creating the destination first if a
+    /// schema for it is given. The response counts the documents copied.
+    pub fn reindex(&self, body: Body) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let req = match serde_json::from_slice::<Reindex>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            match IndexHandler::copy_documents(&cat, req) {
+                Ok(docs_affected) => with_body(DocsAffected { docs_affected }),
+                Err(e @ Error::UnknownIndex(_)) => error_response(StatusCode::NOT_FOUND, e),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    /// The destination is checked for and created under the catalog's write lock so concurrent
+    /// requests can't both create it, the copy itself runs on cloned handles without the catalog locked.
+    fn copy_documents(catalog: &SharedCatalog, req: Reindex) -> Result<u64, Error> {
+        let Reindex { source, dest, rename } = req;
+        let (from, to) = {
+            let mut cat = catalog.write();
+            let from = cat.get_owned_index(&source.index)?;
+            if !cat.exists(&dest.index) {
+                let index = &dest.index;
+                let schema = dest.schema.ok_or_else(|| Error::UnknownIndex(index.clone()))?;
+                let settings = IndexSettings {
+                    primary_key: dest.primary_key,
+                    ..IndexSettings::default()
+                };
+                settings.validate(&schema.0)?;
+                LocalIndex::check_copyable(&from.get_index().schema(), &schema.0, &rename)?;
+                cat.create_index(&dest.index, schema.0, &settings)?;
+            }
+            (from, cat.get_owned_index(&dest.index)?)
+        };
+        to.reindex_from(&from, source.query.unwrap_or(Query::All), &rename)
+    }
+
+    pub fn drop_index(&self, index: String) -> ResponseFuture {
+        let cat = Arc::clone(&self.catalog);
+        let fut = future::lazy(move || {
+            let remote = {
+                let mut cat = cat.write();
+                if cat.aliases().get(&index).is_some() {
+                    let e = Error::QueryError(format!("'{}' is an alias, drop the indexes it points to instead", index));
+                    return Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, e)));
+                }
+                if !cat.exists(&index) && !cat.remote_exists(&index) {
+                    return Either::A(future::ok(empty_with_code(StatusCode::NOT_FOUND)));
+                }
+                let remote = cat.get_remote_index(&index).ok();
+                match cat.remove_index(&index) {
+                    Err(e @ Error::QueryError(_)) => return Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, e))),
+                    Err(e) => return Either::A(future::ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, e))),
+                    Ok(_) => (),
+                }
+                remote
+            };
+
+            match remote {
+                Some(handle) => Either::B(
+                    handle
+                        .drop_index()
+                        .map(|_| empty_with_code(StatusCode::OK))
+                        .or_else(|e| future::ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, Error::IOError(e.to_string())))),
+                ),
+                None => Either::A(future::ok(empty_with_code(StatusCode::OK))),
+            }
+        });
+
+        Box::new(fut)
+    }
+
     pub fn add_document(&self, body: Body, index: String) -> ResponseFuture {
         let cat_clone = Arc::clone(&self.catalog);
         let task = body.concat2().and_then(move |b| {
             let b = serde_json::from_slice::<AddDocument>(&b).unwrap();
+            let (index, missing) = {
+                let cat = cat_clone.read();
+                let index = match cat.resolve_write_index(&index) {
+                    Ok(index) => index,
+                    Err(e) => return Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, e))),
+                };
+                let missing = !cat.exists(&index) && !cat.remote_exists(&index);
+                (index, missing)
+            };
+            if missing {
+                if let Err(e) = cat_clone.write().create_dynamic(&index, &b.document) {
+                    return Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, e)));
+                }
+            }
             let cat = cat_clone.read();
             let location: bool = random();
-            if location && cat.remote_exists(&index) {
+            let add = if location && cat.remote_exists(&index) {
                 let t = cat
                     .add_remote_document(&index, b)
                     .map(|_| empty_with_code(StatusCode::CREATED))
@@ -146,7 +471,8 @@ impl IndexHandler {
                     .or_else(|e| future::ok(error_response(StatusCode::BAD_REQUEST, e)));
 
                 Either::B(t)
-            }
+            };
+            Either::B(add)
         });
 
         Box::new(task)
@@ -156,13 +482,17 @@ impl IndexHandler {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::path::Path;
 
+    use parking_lot::RwLock;
     use pretty_assertions::assert_eq;
     use tokio::prelude::*;
 
     use toshi_types::client::SearchResults;
     use toshi_types::server::IndexOptions;
 
+    use crate::handle::LocalIndex;
+    use crate::handlers::search::tests::wait_json;
     use crate::handlers::SearchHandler;
     use crate::index::tests::*;
 
@@ -183,7 +513,10 @@ mod tests {
          ]"#;
         let handler = IndexHandler::new(Arc::clone(&shared_cat));
 
-        handler.create_index(Body::from(schema), "new_index".into()).wait().unwrap();
+        handler
+            .create_index(Body::from(schema), "new_index".into(), IndexSettings::default())
+            .wait()
+            .unwrap();
         let search = SearchHandler::new(Arc::clone(&shared_cat));
         let docs = search
             .all_docs("new_index".into())
@@ -196,7 +529,224 @@ mod tests {
         let body: SearchResults<Document> = serde_json::from_slice(&docs).unwrap();
 
         assert_eq!(body.hits, 0);
-        remove_dir_all::remove_dir_all("new_index").unwrap();
+        shared_cat.write().remove_index("new_index").unwrap();
+    }
+
+    #[test]
+    fn test_reindex() {
+        let shared_cat = create_test_catalog("test_index");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let body = r#"{
+            "source": { "index": "test_index", "query": { "range": { "test_u64": { "gte": 12 } } } },
+            "dest": {
+                "index": "reindexed_index",
+                "primary_key": "test_u64",
+                "schema": [
+                    { "name": "title", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } },
+                    { "name": "test_u64", "type": "u64", "options": { "indexed": true, "stored": true } }
+                ]
+            },
+            "rename": { "test_text": "title" }
+        }"#;
+        for _ in 0..2 {
+            let resp = handler.reindex(Body::from(body)).wait().unwrap();
+            assert_eq!(wait_json::<DocsAffected>(resp).docs_affected, 3);
+        }
+
+        let search = SearchHandler::new(Arc::clone(&shared_cat));
+        let all: SearchResults<crate::NamedDoc> = wait_json(search.all_docs("reindexed_index".into()).wait().unwrap());
+        assert_eq!(all.hits, 3);
+        let term = r#"{ "query": { "term": { "title": "document" } } }"#;
+        let found: SearchResults<crate::NamedDoc> =
+            wait_json(search.doc_search(Body::from(term), "reindexed_index".into()).wait().unwrap());
+        assert_eq!(found.hits, 2);
+        assert_eq!(found.docs[0].doc.contains_key("test_i64"), false);
+
+        let missing = r#"{ "source": { "index": "test_index" }, "dest": { "index": "nowhere" } }"#;
+        let resp = handler.reindex(Body::from(missing)).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let mut builder = SchemaBuilder::new();
+        builder.add_text_field("title", TEXT);
+        builder.add_u64_field("rank", STORED);
+        shared_cat
+            .write()
+            .create_index("unstored_index", builder.build(), &IndexSettings::default())
+            .unwrap();
+        let unstored = r#"{
+            "source": { "index": "unstored_index" },
+            "dest": {
+                "index": "lossy_index",
+                "schema": [
+                    { "name": "title", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } },
+                    { "name": "rank", "type": "u64", "options": { "stored": true } }
+                ]
+            }
+        }"#;
+        let resp = handler.reindex(Body::from(unstored)).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(!shared_cat.read().exists("lossy_index"));
+        shared_cat.write().remove_index("unstored_index").unwrap();
+        shared_cat.write().remove_index("reindexed_index").unwrap();
+    }
+
+    #[test]
+    fn test_dynamic_mapping() {
+        let shared_cat = create_test_catalog("test_index");
+        shared_cat.write().settings.dynamic_mapping = true;
+        let template = r#"{ "patterns": ["logs-*"], "primary_key": "id", "schema": [
+            { "name": "level", "type": "text", "options": { "indexing": { "record": "basic", "tokenizer": "raw" }, "stored": true } }
+        ] }"#;
+        shared_cat
+            .write()
+            .put_template("logs", serde_json::from_str(template).unwrap())
+            .unwrap();
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+
+        let doc = r#"{ "options": { "commit": true }, "document": { "id": "a1", "level": "warn", "message": "disk full", "at": "2026-10-18T12:00:00Z", "took": 1.5 } }"#;
+        let resp = handler.add_document(Body::from(doc), "logs-app".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        {
+            let cat = shared_cat.read();
+            let index = cat.get_index("logs-app").unwrap();
+            assert_eq!(index.primary_key().is_some(), true);
+            let schema = index.get_index().schema();
+            let type_of = |name: &str| schema.get_field_entry(schema.get_field(name).unwrap()).field_type().value_type();
+            assert_eq!(type_of("at"), Type::Date);
+            assert_eq!(type_of("took"), Type::F64);
+            assert_eq!(type_of("message"), Type::Str);
+        }
+        let search = SearchHandler::new(Arc::clone(&shared_cat));
+        let term = r#"{ "query": { "term": { "message": "disk" } } }"#;
+        let found: SearchResults<crate::NamedDoc> = wait_json(search.doc_search(Body::from(term), "logs-app".into()).wait().unwrap());
+        assert_eq!(found.hits, 1);
+
+        let unknown = r#"{ "document": { "ok": true } }"#;
+        let resp = handler.add_document(Body::from(unknown), "not_inferred".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        shared_cat.write().settings.dynamic_mapping = false;
+        let resp = handler.add_document(Body::from(doc), "logs-off".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            shared_cat.read().exists("not_inferred") || shared_cat.read().exists("logs-off"),
+            false
+        );
+        shared_cat.write().remove_index("logs-app").unwrap();
+    }
+
+    #[test]
+    fn test_add_fields() {
+        let shared_cat = create_test_catalog("test_index");
+        let mut builder = SchemaBuilder::new();
+        builder.add_text_field("title", TEXT | STORED);
+        shared_cat
+            .write()
+            .create_index("evolving_index", builder.build(), &IndexSettings::default())
+            .unwrap();
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let add = |doc: &str| {
+            let body = format!(r#"{{ "options": {{ "commit": true }}, "document": {} }}"#, doc);
+            let resp = handler.add_document(Body::from(body), "evolving_index".into()).wait().unwrap();
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        };
+        add(r#"{ "title": "first" }"#);
+        add(r#"{ "title": "second" }"#);
+
+        let stored = r#"[{ "name": "note", "type": "text", "options": { "stored": true } }]"#;
+        let change: SchemaChange = wait_json(handler.add_fields(Body::from(stored), "evolving_index".into()).wait().unwrap());
+        assert_eq!(
+            change,
+            SchemaChange {
+                schema_version: 1,
+                reindexed: false
+            }
+        );
+        add(r#"{ "title": "third", "note": "kept" }"#);
+
+        let indexed = r#"[{ "name": "rank", "type": "u64", "options": { "indexed": true, "stored": true, "fast": "single" } }]"#;
+        let held = shared_cat.read().get_owned_index("evolving_index").unwrap();
+        let resp = handler.add_fields(Body::from(indexed), "evolving_index".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(Path::new(&IndexCatalog::staging_name("evolving_index", 2)).exists(), false);
+        drop(held);
+        let change: SchemaChange = wait_json(handler.add_fields(Body::from(indexed), "evolving_index".into()).wait().unwrap());
+        assert_eq!(
+            change,
+            SchemaChange {
+                schema_version: 2,
+                reindexed: true
+            }
+        );
+        let change: SchemaChange = wait_json(handler.add_fields(Body::from(indexed), "evolving_index".into()).wait().unwrap());
+        assert_eq!(
+            change,
+            SchemaChange {
+                schema_version: 2,
+                reindexed: false
+            }
+        );
+        assert_eq!(Path::new(&IndexCatalog::staging_name("evolving_index", 2)).exists(), false);
+        add(r#"{ "title": "fourth", "rank": 5 }"#);
+
+        let search = SearchHandler::new(Arc::clone(&shared_cat));
+        let all: SearchResults<crate::NamedDoc> = wait_json(search.all_docs("evolving_index".into()).wait().unwrap());
+        assert_eq!(all.hits, 4);
+        let ranked = r#"{ "query": { "range": { "rank": { "gte": 1 } } } }"#;
+        let found: SearchResults<crate::NamedDoc> =
+            wait_json(search.doc_search(Body::from(ranked), "evolving_index".into()).wait().unwrap());
+        assert_eq!(found.hits, 1);
+        let term = r#"{ "query": { "term": { "title": "first" } } }"#;
+        let found: SearchResults<crate::NamedDoc> = wait_json(search.doc_search(Body::from(term), "evolving_index".into()).wait().unwrap());
+        assert_eq!(found.hits, 1);
+
+        let changed = r#"[{ "name": "title", "type": "text", "options": { "stored": true } }]"#;
+        let resp = handler.add_fields(Body::from(changed), "evolving_index".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = handler.add_fields(Body::from(stored), "missing_index".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        shared_cat.write().remove_index("evolving_index").unwrap();
+    }
+
+    #[test]
+    fn test_get_schema() {
+        let shared_cat = create_test_catalog("test_index");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let resp = handler.get_schema(test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body: SchemaBody = wait_json(resp);
+        let schema = body.0;
+        let text = schema.get_field_entry(schema.get_field("test_text").unwrap());
+        let unindexed = schema.get_field_entry(schema.get_field("test_unindex").unwrap());
+        let int = schema.get_field_entry(schema.get_field("test_i64").unwrap());
+        assert_eq!(schema.fields().len(), 5);
+        assert_eq!(text.is_indexed() && text.is_stored(), true);
+        assert_eq!(unindexed.is_indexed(), false);
+        assert_eq!(int.field_type().value_type(), Type::I64);
+
+        let missing = handler.get_schema("asdf".into()).wait().unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_drop_index() {
+        let shared_cat = create_test_catalog("test_index");
+        let schema = r#"[{ "name": "test_text", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } }]"#;
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+
+        handler
+            .create_index(Body::from(schema), "drop_index".into(), IndexSettings::default())
+            .wait()
+            .unwrap();
+        assert_eq!(shared_cat.read().exists("drop_index"), true);
+
+        let resp = handler.drop_index("drop_index".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(shared_cat.read().exists("drop_index"), false);
+        assert_eq!(std::path::Path::new("drop_index").exists(), false);
+
+        let missing = handler.drop_index("drop_index".into()).wait().unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
     }
 
     #[test]
@@ -224,6 +774,281 @@ mod tests {
         assert_eq!(req.is_ok(), true);
     }
 
+    #[test]
+    fn test_delete_by_query() {
+        let shared_cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let body = r#"{ "query": { "range": { "test_i64": { "gte": 2016 } } } }"#;
+        let resp = handler.delete_by_query(Body::from(body), test_index()).wait().unwrap();
+        let affected: DocsAffected = wait_json(resp);
+        assert_eq!(affected.docs_affected, 2);
+
+        let search = SearchHandler::new(Arc::clone(&shared_cat));
+        let docs: SearchResults<crate::NamedDoc> = wait_json(search.all_docs(test_index()).wait().unwrap());
+        assert_eq!(docs.hits, 3);
+
+        let unkeyed = create_test_catalog("test_index");
+        let handler = IndexHandler::new(Arc::clone(&unkeyed));
+        let resp = handler.delete_by_query(Body::from(body), test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let missing = handler.delete_by_query(Body::from(body), "missing".into()).wait().unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_update_by_query() {
+        let shared_cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let body = r#"{
+            "query": { "range": { "test_i64": { "lt": 0 } } },
+            "updates": { "test_text": { "set": "Archived" }, "test_i64": { "increment": 10 } }
+        }"#;
+        let resp = handler.update_by_query(Body::from(body), test_index()).wait().unwrap();
+        let affected: DocsAffected = wait_json(resp);
+        assert_eq!(affected.docs_affected, 2);
+
+        let resp = handler.get_document(test_index(), "11".into()).wait().unwrap();
+        let doc = wait_json::<FetchedDoc<crate::NamedDoc>>(resp).doc.unwrap();
+        assert_eq!(doc["test_text"][0].text(), Some("Archived"));
+        assert_eq!(doc["test_i64"][0].i64_value(), -2005);
+        assert_eq!(doc["test_facet"].len(), 1);
+
+        let unchanged = r#"{ "query": { "term": { "test_text": "archived" } }, "updates": { "test_text": { "set": "Archived" } } }"#;
+        let resp = handler.update_by_query(Body::from(unchanged), test_index()).wait().unwrap();
+        assert_eq!(wait_json::<DocsAffected>(resp).docs_affected, 0);
+
+        let search = SearchHandler::new(Arc::clone(&shared_cat));
+        let docs: SearchResults<crate::NamedDoc> = wait_json(search.all_docs(test_index()).wait().unwrap());
+        assert_eq!(docs.hits, 5);
+
+        for bad in &[
+            r#"{ "query": { "range": { "test_u64": { "gte": 0 } } }, "updates": { "test_u64": { "set": 1 } } }"#,
+            r#"{ "query": { "range": { "test_u64": { "gte": 0 } } }, "updates": { "test_text": { "increment": 1 } } }"#,
+            r#"{ "query": { "range": { "test_u64": { "gte": 0 } } }, "updates": { "nope": { "set": 1 } } }"#,
+            r#"{ "query": { "range": { "test_u64": { "gte": 0 } } }, "updates": { "test_i64": { "set": "soon" } } }"#,
+        ] {
+            let resp = handler.update_by_query(Body::from(*bad), test_index()).wait().unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        let resp = handler.get_document(test_index(), "11".into()).wait().unwrap();
+        let doc = wait_json::<FetchedDoc<crate::NamedDoc>>(resp).doc.unwrap();
+        assert_eq!(doc["test_i64"][0].i64_value(), -2005);
+
+        let unkeyed = create_test_catalog("test_index");
+        let handler = IndexHandler::new(Arc::clone(&unkeyed));
+        let resp = handler.update_by_query(Body::from(body), test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_primary_key_validation() {
+        let shared_cat = create_test_catalog("test_index");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let schema = r#"[{ "name": "test_text", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } }]"#;
+
+        let resp = handler
+            .create_index(
+                Body::from(schema),
+                "bad_key_index".into(),
+                IndexSettings::with_primary_key("test_text"),
+            )
+            .wait()
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(shared_cat.read().exists("bad_key_index"), false);
+    }
+
+    #[test]
+    fn test_duplicate_key() {
+        let shared_cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let dupe = r#"{"options": {"commit": true }, "document": {"test_text": "Babbaboo!", "test_u64": 10, "test_i64": -10} }"#;
+        let resp = handler.add_document(Body::from(dupe), test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let missing = r#"{"options": {"commit": true }, "document": {"test_text": "Babbaboo!", "test_i64": -10} }"#;
+        let resp = handler.add_document(Body::from(missing), test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let new = r#"{"options": {"commit": true }, "document": {"test_text": "Babbaboo!", "test_u64": 99, "test_i64": -10} }"#;
+        let resp = handler.add_document(Body::from(new), test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let uncommitted = r#"{"document": {"test_text": "Babbaboo!", "test_u64": 100, "test_i64": -10} }"#;
+        let resp = handler.add_document(Body::from(uncommitted), test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = handler.add_document(Body::from(uncommitted), test_index()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let index = shared_cat.read().get_owned_index("test_index").unwrap();
+        let doc = toshi_test::create_test_index()
+            .schema()
+            .parse_document(r#"{"test_u64": 101}"#)
+            .unwrap();
+        index.add_unique(doc.clone()).unwrap();
+        assert!(index.add_unique(doc).is_err());
+        assert_eq!(index.get_reader().searcher().num_docs(), 6);
+        index.commit().unwrap();
+        assert_eq!(index.get_reader().searcher().num_docs(), 8);
+    }
+
+    #[test]
+    fn test_get_document() {
+        let shared_cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+
+        let resp = handler.get_document(test_index(), "12".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let doc: FetchedDoc<crate::NamedDoc> = wait_json(resp);
+        assert_eq!(doc.found, true);
+        assert_eq!(doc.doc.unwrap()["test_text"][0].text(), Some("Test Duckiment 3"));
+
+        let missing = handler.get_document(test_index(), "1".into()).wait().unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        let invalid = handler.get_document(test_index(), "abc".into()).wait().unwrap();
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_multi_get() {
+        let shared_cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let body = r#"{ "ids": ["10", "14", "100"] }"#;
+
+        let resp = handler.multi_get(Body::from(body), test_index()).wait().unwrap();
+        let results: MultiGetResults<crate::NamedDoc> = wait_json(resp);
+        assert_eq!(results.docs.len(), 3);
+        assert_eq!(results.docs[0].found, true);
+        assert_eq!(
+            results.docs[1].doc.as_ref().unwrap()["test_text"][0].text(),
+            Some("Test Document 5")
+        );
+        assert_eq!(results.docs[2].id, "100");
+        assert_eq!(results.docs[2].found, false);
+    }
+
+    #[test]
+    fn test_upsert_document() {
+        let shared_cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let replace = r#"{"options": {"commit": true }, "mode": "upsert", "document": {"test_text": "Replaced", "test_u64": 10} }"#;
+        let resp = handler
+            .update_document(Body::from(replace), test_index(), "10".into())
+            .wait()
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let doc: FetchedDoc<crate::NamedDoc> = wait_json(handler.get_document(test_index(), "10".into()).wait().unwrap());
+        let doc = doc.doc.unwrap();
+        assert_eq!(doc["test_text"][0].text(), Some("Replaced"));
+        assert_eq!(doc.contains_key("test_i64"), false);
+
+        let create = r#"{"options": {"commit": true }, "mode": "upsert", "document": {"test_text": "Brand new"} }"#;
+        let resp = handler
+            .update_document(Body::from(create), test_index(), "50".into())
+            .wait()
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let doc: FetchedDoc<crate::NamedDoc> = wait_json(handler.get_document(test_index(), "50".into()).wait().unwrap());
+        assert_eq!(doc.doc.unwrap()["test_u64"][0].u64_value(), 50);
+        assert_eq!(
+            shared_cat
+                .read()
+                .get_index("test_index")
+                .unwrap()
+                .get_reader()
+                .searcher()
+                .num_docs(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_partial_update() {
+        let shared_cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+        let partial = r#"{"options": {"commit": true }, "document": {"test_text": "Partially updated"} }"#;
+        let resp = handler
+            .update_document(Body::from(partial), test_index(), "12".into())
+            .wait()
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let doc: FetchedDoc<crate::NamedDoc> = wait_json(handler.get_document(test_index(), "12".into()).wait().unwrap());
+        let doc = doc.doc.unwrap();
+        assert_eq!(doc["test_text"][0].text(), Some("Partially updated"));
+        assert_eq!(doc["test_i64"].len(), 1);
+        assert_eq!(
+            shared_cat
+                .read()
+                .get_index("test_index")
+                .unwrap()
+                .get_reader()
+                .searcher()
+                .num_docs(),
+            5
+        );
+
+        let missing = handler
+            .update_document(Body::from(partial), test_index(), "99".into())
+            .wait()
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let mismatch = r#"{"document": {"test_u64": 11} }"#;
+        let resp = handler
+            .update_document(Body::from(mismatch), test_index(), "12".into())
+            .wait()
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        for update in &[
+            r#"{"document": {"test_unindex": "first"} }"#,
+            r#"{"options": {"commit": true }, "document": {"test_text": "second"} }"#,
+        ] {
+            let resp = handler
+                .update_document(Body::from(*update), test_index(), "13".into())
+                .wait()
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        let doc: FetchedDoc<crate::NamedDoc> = wait_json(handler.get_document(test_index(), "13".into()).wait().unwrap());
+        let doc = doc.doc.unwrap();
+        assert_eq!(doc["test_unindex"][0].text(), Some("first"));
+        assert_eq!(doc["test_text"][0].text(), Some("second"));
+    }
+
+    #[test]
+    fn test_partial_update_unstored() {
+        let mut builder = tantivy::schema::Schema::builder();
+        let id = builder.add_u64_field("id", tantivy::schema::INDEXED | tantivy::schema::STORED);
+        let body = builder.add_text_field("body", tantivy::schema::TEXT);
+        let index = tantivy::Index::create_in_ram(builder.build());
+        LocalIndex::write_settings(&index, &IndexSettings::with_primary_key("id")).unwrap();
+        let mut writer = index.writer(30_000_000).unwrap();
+        writer.add_document(tantivy::doc!(id => 1u64, body => "not stored"));
+        writer.commit().unwrap();
+        drop(writer);
+        let shared_cat = Arc::new(RwLock::new(IndexCatalog::with_index("unstored".into(), index).unwrap()));
+        let handler = IndexHandler::new(Arc::clone(&shared_cat));
+
+        let partial = r#"{"options": {"commit": true }, "document": {"body": "replaced"} }"#;
+        let resp = handler
+            .update_document(Body::from(partial), "unstored".into(), "1".into())
+            .wait()
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let upsert = r#"{"options": {"commit": true }, "mode": "upsert", "document": {"body": "replaced"} }"#;
+        let resp = handler
+            .update_document(Body::from(upsert), "unstored".into(), "1".into())
+            .wait()
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let by_query = r#"{ "query": { "term": { "id": 1 } }, "updates": { "body": { "set": "replaced" } } }"#;
+        let resp = handler.update_by_query(Body::from(by_query), "unstored".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
     #[test]
     fn test_bad_json() {
         let shared_cat = create_test_catalog("test_index");
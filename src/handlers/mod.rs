@@ -5,9 +5,11 @@ use hyper::Body;
 pub use self::{bulk::BulkHandler, index::IndexHandler, search::SearchHandler, summary::summary};
 
 pub mod bulk;
+pub mod catalog;
 pub mod index;
 pub mod root;
 pub mod search;
+pub mod snapshot;
 pub mod summary;
 
 pub type BaseFuture = dyn Future<Item = Response<Body>, Error = hyper::Error> + Send;
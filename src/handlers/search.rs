@@ -3,15 +3,19 @@ use std::sync::Arc;
 use futures::future::Either;
 use futures::stream::futures_unordered;
 use http::StatusCode;
-use hyper::Body;
+use hyper::{Body, Response};
 use tokio::prelude::*;
 use tracing::*;
 
 use crate::handlers::ResponseFuture;
 use crate::index::SharedCatalog;
-use crate::utils::{empty_with_code, with_body};
-use crate::SearchResults;
-use toshi_types::query::Search;
+use crate::scroll::parse_keep_alive;
+use crate::utils::{empty_with_code, error_response, with_body};
+use crate::{NamedDoc, SearchResults};
+use toshi_types::client::{CountResult, MultiSearchError, MultiSearchItem, MultiSearchResults};
+use toshi_types::error::Error;
+use toshi_types::query::{Query, Search};
+use toshi_types::server::{MultiSearchHeader, ScrollRequest};
 
 #[derive(Clone)]
 pub struct SearchHandler {
@@ -24,41 +28,257 @@ impl SearchHandler {
     }
 
     #[inline]
-    fn fold_results(results: Vec<SearchResults>) -> SearchResults {
-        results.into_iter().sum()
+    fn fold_results(results: Vec<SearchResults>, req: &Search) -> SearchResults {
+        results
+            .into_iter()
+            .sum::<SearchResults>()
+            .page(req.from, req.limit, &req.sort_orders())
+            .finish_aggregations(&req.aggs)
+            .finish_facets(&req.facets)
     }
 
+    /// Searches every index matching `index`, which can be a comma separated list of names and glob
+    /// patterns such as `logs-2026-*,archive`. Hits from all of them are merged into one page, and the
+    /// search fails if any of them fails rather than returning a partial page. A search of a single
+    /// index by its name keeps the original behavior instead, a shard that fails adds no hits.
     pub fn doc_search(&self, body: Body, index: String) -> ResponseFuture {
         let catalog = Arc::clone(&self.catalog);
         Box::new(
             body.concat2()
                 .map(|b| serde_json::from_slice::<Search>(&b).unwrap())
                 .and_then(move |req| {
-                    let c = catalog.read();
-                    let req = if req.query.is_none() { Search::all_docs() } else { req };
-                    info!("Query: {:?}", req);
-                    if c.exists(&index) {
-                        let mut tasks = vec![future::Either::A(c.search_local_index(&index, req.clone()))];
-                        if c.remote_exists(&index) {
-                            tasks.push(future::Either::B(c.search_remote_index(&index, req)));
-                        }
-                        Either::A(
-                            futures_unordered(tasks)
-                                .then(|next| match next {
-                                    Ok(v) => Ok(v),
-                                    Err(_) => Ok(Vec::new()),
-                                })
-                                .concat2()
-                                .map(SearchHandler::fold_results)
-                                .map(with_body),
-                        )
-                    } else {
-                        Either::B(future::ok(empty_with_code(StatusCode::NOT_FOUND)))
-                    }
+                    SearchHandler::search(&catalog, &index, req).then(|result| match result {
+                        Ok(results) => Ok(with_body(results)),
+                        Err(Error::UnknownIndex(_)) => Ok(empty_with_code(StatusCode::NOT_FOUND)),
+                        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+                    })
                 }),
         )
     }
 
+    fn search(catalog: &SharedCatalog, index: &str, req: Search) -> impl Future<Item = SearchResults, Error = Error> + Send {
+        let c = catalog.read();
+        let req = if req.query.is_none() {
+            Search {
+                query: Some(Query::All),
+                ..req
+            }
+        } else {
+            req
+        };
+        info!("Query: {:?}", req);
+        if let Err(e) = req.window() {
+            return Either::B(future::err(e));
+        }
+        let indexes = c.resolve_indexes(index);
+        if indexes.is_empty() {
+            return Either::B(future::err(Error::UnknownIndex(index.into())));
+        }
+        let shard_req = req.for_shards();
+        let mut tasks = Vec::new();
+        for index in &indexes {
+            if c.exists(index) {
+                tasks.push(future::Either::A(c.search_local_index(index, shard_req.clone())));
+            }
+            if c.remote_exists(index) {
+                tasks.push(future::Either::B(c.search_remote_index(index, shard_req.clone())));
+            }
+        }
+        let concrete = indexes.len() == 1 && indexes[0] == index;
+        Either::A(
+            futures_unordered(tasks)
+                .then(move |next| match next {
+                    Err(_) if concrete => Ok(Vec::new()),
+                    next => next,
+                })
+                .concat2()
+                .map(move |results| SearchHandler::fold_results(results, &req)),
+        )
+    }
+
+    /// Runs a batch of searches given as newline delimited JSON, a header line such as
+    /// `{ "index": "logs-*" }` followed by the search body. The searches are joined into one future
+    /// rather than spawned, so they wait on remote shards together, and a failed one is reported in
+    /// its place without failing the rest.
+    pub fn multi_search(&self, body: Body) -> ResponseFuture {
+        let catalog = Arc::clone(&self.catalog);
+        let fut = body.concat2().and_then(move |b| {
+            let text = String::from_utf8_lossy(&b).into_owned();
+            let lines: Vec<&str> = text.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+            let items = lines.chunks(2).map(|pair| {
+                let parsed = serde_json::from_str::<MultiSearchHeader>(pair[0])
+                    .map_err(Error::from)
+                    .and_then(|header| match pair.get(1) {
+                        Some(body) => Ok((header.index, serde_json::from_str::<Search>(body)?)),
+                        None => Err(Error::QueryError("Missing search body after header".into())),
+                    });
+                let catalog = Arc::clone(&catalog);
+                future::lazy(move || {
+                    future::result(parsed)
+                        .and_then(move |(index, req)| SearchHandler::search(&catalog, &index, req))
+                        .then(|result| -> Result<MultiSearchItem<NamedDoc>, ()> {
+                            Ok(match result {
+                                Ok(results) => MultiSearchItem::Results(results),
+                                Err(e) => {
+                                    let status = match e {
+                                        Error::UnknownIndex(_) => StatusCode::NOT_FOUND,
+                                        _ => StatusCode::BAD_REQUEST,
+                                    };
+                                    MultiSearchItem::Error(MultiSearchError {
+                                        status: status.as_u16(),
+                                        error: e.to_string(),
+                                    })
+                                }
+                            })
+                        })
+                })
+            });
+            future::join_all(items.collect::<Vec<_>>())
+                .map(|responses| with_body(MultiSearchResults { responses }))
+                .or_else(|_| Ok(empty_with_code(StatusCode::INTERNAL_SERVER_ERROR)))
+        });
+        Box::new(fut)
+    }
+
+    /// Counts the matching documents on every shard of every index the name resolves to, local or
+    /// remote, the same indexes a search would cover. No documents are scored or fetched.
+    pub fn count(&self, body: Body, index: String) -> ResponseFuture {
+        let catalog = Arc::clone(&self.catalog);
+        let fut = body.concat2().and_then(move |b| {
+            let req = match serde_json::from_slice::<Search>(&b) {
+                Ok(v) => v,
+                Err(e) => return Either::A(future::ok(error_response(StatusCode::BAD_REQUEST, Error::from(e)))),
+            };
+            let c = catalog.read();
+            let indexes = c.resolve_indexes(&index);
+            if indexes.is_empty() {
+                return Either::A(future::ok(empty_with_code(StatusCode::NOT_FOUND)));
+            }
+            let mut tasks = Vec::new();
+            for index in &indexes {
+                if c.exists(index) {
+                    let query = req.query.clone().unwrap_or(Query::All);
+                    let local = c.count_local_index(index, query);
+                    tasks.push(Either::A(
+                        local.map_err(|e| (SearchHandler::count_status(&e, StatusCode::INTERNAL_SERVER_ERROR), e)),
+                    ));
+                }
+                if c.remote_exists(index) {
+                    let remote = c.count_remote_index(index, req.clone());
+                    tasks.push(Either::B(
+                        remote.map_err(|e| (SearchHandler::count_status(&e, StatusCode::BAD_GATEWAY), e)),
+                    ));
+                }
+            }
+            Either::B(
+                future::join_all(tasks)
+                    .map(|counts| {
+                        with_body(CountResult {
+                            count: counts.iter().sum(),
+                        })
+                    })
+                    .or_else(|(status, e)| future::ok(error_response(status, e))),
+            )
+        });
+
+        Box::new(fut)
+    }
+
+    /// A query that can't be run is the request's fault wherever it fails, any other failure is
+    /// `otherwise`, which for a remote shard means the node couldn't be reached or failed itself.
+    fn count_status(e: &Error, otherwise: StatusCode) -> StatusCode {
+        match e {
+            Error::QueryError(_) | Error::UnknownIndexField(_) => StatusCode::BAD_REQUEST,
+            _ => otherwise,
+        }
+    }
+
+    pub fn explain(&self, body: Body, index: String, id: String) -> ResponseFuture {
+        let catalog = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let req = match serde_json::from_slice::<Search>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            let query = req.query.unwrap_or(Query::All);
+            let explained = catalog.read().get_index(&index).and_then(|handle| handle.explain(&id, query));
+            match explained {
+                Ok(result) => with_body(result),
+                Err(e @ Error::UnknownIndex(_)) | Err(e @ Error::UnknownDocument(_)) => error_response(StatusCode::NOT_FOUND, e),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    /// Opens a scroll over a single local index and returns its first batch, along with the scroll
+    /// id to fetch the rest from `_scroll`. `keep_alive` is how long the scroll is kept between calls.
+    pub fn open_scroll(&self, body: Body, index: String, keep_alive: String) -> ResponseFuture {
+        let catalog = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let mut req = match serde_json::from_slice::<Search>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            if req.query.is_none() {
+                req.query = Some(Query::All);
+            }
+            let (aggs, facets) = (req.aggs.clone(), req.facets.clone());
+            let c = catalog.read();
+            let opened = parse_keep_alive(&keep_alive)
+                .and_then(|keep_alive| c.scrolls().open(c.get_index(&index)?, req, keep_alive))
+                .map(|results| results.finish_aggregations(&aggs).finish_facets(&facets));
+            SearchHandler::scroll_response(opened)
+        });
+
+        Box::new(fut)
+    }
+
+    /// Returns the next batch of an open scroll.
+    pub fn scroll(&self, body: Body) -> ResponseFuture {
+        let catalog = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let req = match serde_json::from_slice::<ScrollRequest>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            let keep_alive = match req.scroll.as_ref().map(|s| parse_keep_alive(s)).transpose() {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+            };
+            SearchHandler::scroll_response(catalog.read().scrolls().next(&req.scroll_id, keep_alive))
+        });
+
+        Box::new(fut)
+    }
+
+    /// Releases an open scroll before it expires.
+    pub fn clear_scroll(&self, body: Body) -> ResponseFuture {
+        let catalog = Arc::clone(&self.catalog);
+        let fut = body.concat2().map(move |b| {
+            let req = match serde_json::from_slice::<ScrollRequest>(&b) {
+                Ok(v) => v,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, Error::from(e)),
+            };
+            if catalog.read().scrolls().clear(&req.scroll_id) {
+                empty_with_code(StatusCode::OK)
+            } else {
+                error_response(StatusCode::NOT_FOUND, Error::UnknownScroll(req.scroll_id))
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    fn scroll_response(results: crate::Result<SearchResults>) -> Response<Body> {
+        match results {
+            Ok(results) => with_body(results),
+            Err(e @ Error::UnknownIndex(_)) | Err(e @ Error::UnknownScroll(_)) => error_response(StatusCode::NOT_FOUND, e),
+            Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+        }
+    }
+
     pub fn all_docs(&self, index: String) -> ResponseFuture {
         let body = Body::from(serde_json::to_vec(&Search::all_docs()).unwrap());
         self.doc_search(body, index)
@@ -67,12 +287,13 @@ impl SearchHandler {
 
 #[cfg(test)]
 pub mod tests {
-    use hyper::Response;
     use pretty_assertions::assert_eq;
     use serde::de::DeserializeOwned;
 
+    use crate::handle::IndexHandle;
     use crate::handlers::ResponseFuture;
     use crate::index::tests::*;
+    use toshi_types::client::ExplainResult;
     use toshi_types::query::*;
 
     use super::*;
@@ -108,6 +329,263 @@ pub mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_total_hits() {
+        let term = KeyValue::new("test_text".into(), "document".into());
+        let term_query = Query::Exact(ExactTerm::new(term));
+        let search = Search::new(Some(term_query), None, 1);
+        let body: SearchResults = wait_json(run_query(search, "test_index").wait().unwrap());
+        assert_eq!(body.hits, 1);
+        assert_eq!(body.total_hits, 3);
+
+        let shard: SearchResults = serde_json::from_str(r#"{ "hits": 2, "total_hits": 40, "docs": [] }"#).unwrap();
+        let merged = vec![body, shard].into_iter().sum::<SearchResults>().page(0, 10, &[]);
+        assert_eq!(merged.total_hits, 43);
+    }
+
+    #[test]
+    fn test_terms_on_tokenized_text() {
+        let body = r#"{ "query": null, "aggs": { "words": { "terms": { "field": "test_text" } } } }"#;
+        let req: Search = serde_json::from_str(body).unwrap();
+        let cat = create_test_catalog("test_index");
+        let results = cat.read().get_index("test_index").unwrap().search_index(Search {
+            query: Some(Query::All),
+            ..req
+        });
+        assert!(results.is_err());
+    }
+
+    #[test]
+    fn test_failing_index_fails_search() {
+        let body = r#"{ "query": null, "sort": [{ "field": "test_text" }] }"#;
+        let handler = SearchHandler::new(create_test_catalog("test_index"));
+        let resp = handler.doc_search(Body::from(body), "test_index*".into()).wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let docs: SearchResults = wait_json(handler.doc_search(Body::from(body), "test_index".into()).wait().unwrap());
+        assert_eq!(docs.hits, 0);
+    }
+
+    #[test]
+    fn test_result_window_too_large() {
+        let search = Search::builder().with_from(usize::MAX).with_limit(10).build();
+        let resp = run_query(search, "test_index").wait().unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_aggregations() {
+        let body = r#"{ "query": { "term": { "test_text": "document" } }, "limit": 1, "aggs": {
+            "values": { "terms": { "field": "test_u64", "size": 2 } },
+            "stats": { "metrics": { "field": "test_u64" } } } }"#;
+        let req: Search = serde_json::from_str(body).unwrap();
+        let handler = SearchHandler::new(create_sortable_catalog("test_index"));
+        let local: SearchResults = wait_json(
+            handler
+                .doc_search(Body::from(serde_json::to_vec(&req).unwrap()), "test_index".into())
+                .wait()
+                .unwrap(),
+        );
+        let results = SearchHandler::fold_results(vec![local], &req);
+        assert_eq!(results.hits, 1);
+
+        let json = serde_json::to_value(&results.aggregations).unwrap();
+        let expected = serde_json::json!({
+            "values": { "terms": { "buckets": [ { "key": 10, "doc_count": 1 }, { "key": 13, "doc_count": 1 } ], "sum_other_doc_count": 1 } },
+            "stats": { "metrics": { "count": 3, "min": 10.0, "max": 14.0, "sum": 37.0, "avg": 37.0 / 3.0, "cardinality": 3 } }
+        });
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn test_merge_shard_aggregations() {
+        let req = Search::builder()
+            .with_aggregation("values", Aggregation::terms("test_u64", 1))
+            .build();
+        let cat = create_sortable_catalog("test_index");
+        let local = cat.read().get_index("test_index").unwrap().search_index(req.for_shards()).unwrap();
+        let shard: SearchResults = serde_json::from_str(
+            r#"{ "hits": 0, "docs": [], "aggregations": {
+                "values": { "terms": { "buckets": [ { "key": 14, "doc_count": 3 } ], "sum_other_doc_count": 2 } } } }"#,
+        )
+        .unwrap();
+        let results = SearchHandler::fold_results(vec![local, shard], &req);
+
+        let json = serde_json::to_value(&results.aggregations["values"]).unwrap();
+        let expected = serde_json::json!({ "terms": { "buckets": [ { "key": 14, "doc_count": 4 } ], "sum_other_doc_count": 6 } });
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn test_merge_shard_histograms() {
+        let body = r#"{ "query": null, "aggs": { "hist": { "histogram": { "field": "test_u64", "interval": 5,
+            "aggs": { "stats": { "metrics": { "field": "test_i64" } } } } } } }"#;
+        let req: Search = serde_json::from_str(body).unwrap();
+        let cat = create_sortable_catalog("test_index");
+        let local = cat.read().get_index("test_index").unwrap().search_index(Search {
+            query: Some(Query::All),
+            ..req.for_shards()
+        });
+        let shard: SearchResults = serde_json::from_str(
+            r#"{ "hits": 0, "docs": [], "aggregations": { "hist": { "histogram": { "buckets": [
+                { "key": 15.0, "doc_count": 1, "aggregations": { "stats": { "metrics":
+                    { "count": 1, "min": 1.0, "max": 1.0, "sum": 1.0, "avg": 1.0, "cardinality": 1 } } } } ] } } } }"#,
+        )
+        .unwrap();
+        let results = SearchHandler::fold_results(vec![local.unwrap(), shard], &req);
+
+        let json = serde_json::to_value(&results.aggregations["hist"]).unwrap();
+        let expected = serde_json::json!({ "histogram": { "buckets": [
+            { "key": 10.0, "doc_count": 5, "aggregations": { "stats": { "metrics":
+                { "count": 5, "min": -2017.0, "max": 2018.0, "sum": 2016.0, "avg": 403.2, "cardinality": 5 } } } },
+            { "key": 15.0, "doc_count": 1, "aggregations": { "stats": { "metrics":
+                { "count": 1, "min": 1.0, "max": 1.0, "sum": 1.0, "avg": 1.0, "cardinality": 1 } } } }
+        ] } });
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn test_invalid_aggregation() {
+        let cat = create_test_catalog("test_index");
+        let index = cat.read();
+        let index = index.get_index("test_index").unwrap();
+        let nested = Aggregation::range("test_u64", vec![]).with_aggregation("words", Aggregation::terms("test_text", 5));
+        for agg in &[Aggregation::metrics("test_text"), Aggregation::histogram("test_u64", 0.0), nested] {
+            let req = Search::builder().with_aggregation("bad", agg.clone()).build();
+            match index.search_index(req) {
+                Err(Error::QueryError(_)) => (),
+                other => panic!("Expected a query error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_index_search() {
+        let cat = create_test_catalog("test_index");
+        for name in &["logs-2026-01", "logs-2026-02", "logs-2025-12"] {
+            cat.write().add_index((*name).to_string(), toshi_test::create_test_index()).unwrap();
+        }
+        let handler = SearchHandler::new(Arc::clone(&cat));
+        let search = |req: &Search| -> SearchResults {
+            let body = Body::from(serde_json::to_vec(req).unwrap());
+            wait_json(handler.doc_search(body, "logs-2026-*,test_index".into()).wait().unwrap())
+        };
+
+        let term = KeyValue::new("test_text".into(), "document".into());
+        let req = Search::builder()
+            .with_query(Query::Exact(ExactTerm::new(term)))
+            .with_limit(4)
+            .build();
+        let mut results = search(&req);
+        assert_eq!(results.total_hits, 9);
+        let mut seen = Vec::new();
+        while !results.docs.is_empty() {
+            let scores: Vec<f32> = results.docs.iter().filter_map(|d| d.score).collect();
+            assert!(scores.windows(2).all(|w| w[0] >= w[1]));
+            for doc in &results.docs {
+                let cursor = doc.cursor.as_ref().unwrap();
+                seen.push((doc.index.clone().unwrap(), cursor.segment, cursor.doc));
+            }
+            let after = results.docs.last().and_then(|d| d.cursor.clone()).unwrap();
+            results = search(&Search {
+                search_after: Some(after),
+                ..req.clone()
+            });
+        }
+        let returned = seen.len();
+        seen.sort();
+        seen.dedup();
+        assert_eq!((returned, seen.len()), (9, 9));
+        let indexes: Vec<&str> = seen.iter().map(|(index, _, _)| index.as_str()).collect();
+        assert!(!indexes.contains(&"logs-2025-12"));
+        assert!(indexes.contains(&"logs-2026-02") && indexes.contains(&"test_index"));
+
+        let missing = handler.doc_search(Body::from("{}"), "nothing-*".into()).wait().unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_multi_search() {
+        let cat = create_test_catalog("test_index");
+        let handler = SearchHandler::new(Arc::clone(&cat));
+        let body = r#"
+            { "index": "test_index" }
+            { "query": { "term": { "test_text": "document" } }, "limit": 1 }
+            { "index": "missing" }
+            {}
+            { "index": "test_index" }
+            { "query": { "nope": {} } }
+            { "index": "test_index" }
+            {}
+        "#;
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let resp = rt.block_on(handler.multi_search(Body::from(body))).unwrap();
+        let results: MultiSearchResults<NamedDoc> = wait_json(resp);
+        assert_eq!(results.responses.len(), 4);
+        match &results.responses[0] {
+            MultiSearchItem::Results(r) => assert_eq!((r.hits, r.total_hits), (1, 3)),
+            other => panic!("Expected results, got {:?}", other),
+        }
+        match &results.responses[1] {
+            MultiSearchItem::Error(e) => assert_eq!(e.status, 404),
+            other => panic!("Expected an error, got {:?}", other),
+        }
+        match &results.responses[2] {
+            MultiSearchItem::Error(e) => assert_eq!(e.status, 400),
+            other => panic!("Expected an error, got {:?}", other),
+        }
+        match &results.responses[3] {
+            MultiSearchItem::Results(r) => assert_eq!(r.total_hits, 5),
+            other => panic!("Expected results, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_scroll() {
+        let cat = create_test_catalog("test_index");
+        let handler = SearchHandler::new(Arc::clone(&cat));
+        let body = Body::from(r#"{ "limit": 3 }"#);
+        let first: SearchResults = wait_json(handler.open_scroll(body, "test_index".into(), "1m".into()).wait().unwrap());
+        let scroll_id = first.scroll_id.clone().unwrap();
+        assert_eq!(first.hits, 3);
+
+        let next = format!(r#"{{ "scroll_id": "{}", "scroll": "30s" }}"#, scroll_id);
+        let second: SearchResults = wait_json(handler.scroll(Body::from(next.clone())).wait().unwrap());
+        assert_eq!(second.hits, 2);
+        let clear = handler.clear_scroll(Body::from(next.clone())).wait().unwrap();
+        assert_eq!(clear.status(), StatusCode::OK);
+        let gone = handler.scroll(Body::from(next)).wait().unwrap();
+        assert_eq!(gone.status(), StatusCode::NOT_FOUND);
+
+        let bad = handler
+            .open_scroll(Body::from("{}"), "test_index".into(), "soon".into())
+            .wait()
+            .unwrap();
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_count() {
+        let cat = create_test_catalog("test_index");
+        let handler = SearchHandler::new(Arc::clone(&cat));
+        let body = r#"{ "query" : { "term": { "test_text": "document" } } }"#;
+        let result: CountResult = wait_json(handler.count(Body::from(body), "test_index".into()).wait().unwrap());
+        assert_eq!(result.count, 3);
+
+        let all: CountResult = wait_json(handler.count(Body::from("{}"), "test_index".into()).wait().unwrap());
+        assert_eq!(all.count, 5);
+
+        let missing = handler.count(Body::from(body), "asdf".into()).wait().unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let unknown_field = r#"{ "query" : { "term": { "asdf": "document" } } }"#;
+        let bad = handler.count(Body::from(unknown_field), "test_index".into()).wait().unwrap();
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            SearchHandler::count_status(&Error::IOError("disk".into()), StatusCode::BAD_GATEWAY),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
     #[test]
     fn test_phrase_query() {
         let terms = TermPair::new(vec!["test".into(), "document".into()], None);
@@ -203,6 +681,41 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_merge_shard_facets() {
+        let body = r#"{ "query": null, "facets": { "test_facet": { "paths": ["/cat", "/dog", "/cat"], "size": 2 } } }"#;
+        let req: Search = serde_json::from_str(body).unwrap();
+        let cat = create_test_catalog("test_index");
+        let local = cat.read().get_index("test_index").unwrap().search_index(Search {
+            query: Some(Query::All),
+            ..req.for_shards()
+        });
+        let local = local.unwrap();
+        let counts: Vec<(String, u64)> = local.facet_fields["test_facet"]
+            .iter()
+            .map(|kv| (kv.field.clone(), kv.value))
+            .collect();
+        assert_eq!(counts.len(), 4);
+
+        let shard: SearchResults = serde_json::from_str(
+            r#"{ "hits": 0, "docs": [], "facets": [{ "/dog/cat2": 3 }], "facet_fields": { "test_facet": [{ "/dog/cat2": 3 }] } }"#,
+        )
+        .unwrap();
+        let results = SearchHandler::fold_results(vec![local, shard], &req);
+        let counts: Vec<(String, u64)> = results.facet_fields["test_facet"]
+            .iter()
+            .map(|kv| (kv.field.clone(), kv.value))
+            .collect();
+        assert_eq!(counts, vec![("/dog/cat2".into(), 4), ("/cat/cat2".into(), 2)]);
+        let flat: Vec<(String, u64)> = results.facets.iter().map(|kv| (kv.field.clone(), kv.value)).collect();
+        assert_eq!(flat, counts);
+
+        let unknown = Search::builder()
+            .with_facets(FacetQuery::with_terms("asdf".into(), vec!["/".into()]))
+            .build();
+        assert!(cat.read().get_index("test_index").unwrap().search_index(unknown).is_err());
+    }
+
     #[test]
     fn test_raw_query() -> Result<(), serde_json::Error> {
         let body = r#"test_text:"Duckiment""#;
@@ -287,6 +800,134 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_pagination() {
+        let handler = SearchHandler::new(create_sortable_catalog("test_index"));
+        let page = |search: Search| -> SearchResults {
+            wait_json(
+                handler
+                    .doc_search(Body::from(serde_json::to_vec(&search).unwrap()), "test_index".into())
+                    .wait()
+                    .unwrap(),
+            )
+        };
+        let everything = page(Search::all_docs());
+        let first = page(Search::builder().with_limit(2).build());
+        let second = page(Search::builder().with_limit(2).with_from(2).build());
+        let cursor = first.docs.last().and_then(|d| d.cursor.clone()).unwrap();
+        let after = page(Search::builder().with_limit(2).with_search_after(cursor).build());
+
+        let ids = |r: &SearchResults| -> Vec<u64> { r.docs.iter().map(|d| d.doc["test_u64"][0].u64_value()).collect() };
+        assert_eq!(first.hits, 2);
+        assert_eq!(first.total_hits, 5);
+        assert_eq!(after.total_hits, 5);
+        assert_eq!(ids(&first), ids(&everything)[..2].to_vec());
+        assert_eq!(ids(&second), ids(&everything)[2..4].to_vec());
+        assert_eq!(ids(&after), ids(&second));
+    }
+
+    #[test]
+    fn test_sorted_search() {
+        let handler = SearchHandler::new(create_sortable_catalog("test_index"));
+        let page = |search: Search| -> SearchResults {
+            wait_json(
+                handler
+                    .doc_search(Body::from(serde_json::to_vec(&search).unwrap()), "test_index".into())
+                    .wait()
+                    .unwrap(),
+            )
+        };
+        let sort = SortField::new("test_i64", Order::Desc);
+        let first = page(Search::builder().with_limit(2).with_sort(sort.clone()).build());
+        let values: Vec<SortValue> = first.docs.iter().flat_map(|d| d.sort.clone()).collect();
+        assert_eq!(values, vec![SortValue::I64(2018), SortValue::I64(2016)]);
+
+        let cursor = first.docs.last().and_then(|d| d.cursor.clone()).unwrap();
+        let cursor: Cursor = serde_json::from_value(serde_json::to_value(cursor).unwrap()).unwrap();
+        let next = page(Search::builder().with_limit(2).with_sort(sort).with_search_after(cursor).build());
+        let values: Vec<SortValue> = next.docs.iter().flat_map(|d| d.sort.clone()).collect();
+        assert_eq!(values, vec![SortValue::I64(2014), SortValue::I64(-2015)]);
+    }
+
+    #[test]
+    fn test_field_projection() {
+        let handler = SearchHandler::new(create_sortable_catalog("test_index"));
+        let page = |search: Search| -> SearchResults {
+            wait_json(
+                handler
+                    .doc_search(Body::from(serde_json::to_vec(&search).unwrap()), "test_index".into())
+                    .wait()
+                    .unwrap(),
+            )
+        };
+        let included = page(Search::builder().with_includes(vec!["test_text".into(), "test_u64".into()]).build());
+        let keys: Vec<&String> = included.docs[0].doc.keys().collect();
+        assert_eq!(keys, vec!["test_text", "test_u64"]);
+
+        let excluded = page(
+            Search::builder()
+                .with_excludes(vec!["test_text".into(), "test_facet".into()])
+                .build(),
+        );
+        let keys: Vec<&String> = excluded.docs[0].doc.keys().collect();
+        assert_eq!(keys, vec!["test_i64", "test_u64", "test_unindex"]);
+
+        let body = r#"{ "fields": ["test_unindex"] }"#;
+        let shorthand = page(serde_json::from_str(body).unwrap());
+        assert_eq!(shorthand.docs[0].doc.len(), 1);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let term = KeyValue::new("test_text".into(), "document".into());
+        let mut highlight = Highlight::new(vec!["test_text"]);
+        highlight.pre_tag = "<em>".into();
+        highlight.post_tag = "</em>".into();
+        let search = Search::builder()
+            .with_query(Query::Exact(ExactTerm::new(term)))
+            .with_highlight(highlight)
+            .build();
+        let results: SearchResults = wait_json(run_query(search, "test_index").wait().unwrap());
+
+        assert_eq!(results.hits, 3);
+        for doc in results.docs {
+            assert_eq!(doc.highlights["test_text"].contains("<em>Document</em>"), true);
+        }
+    }
+
+    #[test]
+    fn test_explain_flag() {
+        let term = KeyValue::new("test_text".into(), "document".into());
+        let search = Search::builder()
+            .with_query(Query::Exact(ExactTerm::new(term)))
+            .with_explain(true)
+            .build();
+        let results: SearchResults = wait_json(run_query(search, "test_index").wait().unwrap());
+
+        for doc in results.docs {
+            let explanation = doc.explanation.unwrap();
+            assert_eq!(explanation["value"].as_f64().unwrap() as f32, doc.score.unwrap());
+            assert_eq!(explanation["details"].as_array().is_some(), true);
+        }
+    }
+
+    #[test]
+    fn test_explain_endpoint() {
+        let cat = create_test_catalog_with_key("test_index", "test_u64");
+        let handler = SearchHandler::new(Arc::clone(&cat));
+        let body = r#"{ "query" : { "term": { "test_text": "document" } } }"#;
+
+        let matched: ExplainResult = wait_json(handler.explain(Body::from(body), "test_index".into(), "10".into()).wait().unwrap());
+        assert_eq!(matched.matched, true);
+        assert_eq!(matched.explanation.is_some(), true);
+
+        let missed: ExplainResult = wait_json(handler.explain(Body::from(body), "test_index".into(), "11".into()).wait().unwrap());
+        assert_eq!(missed.matched, false);
+
+        let unknown = handler.explain(Body::from(body), "test_index".into(), "99".into()).wait().unwrap();
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+    }
+
     #[test]
     fn test_bool_query() -> Result<(), serde_json::Error> {
         let test_json = r#"{"query": { "bool": {
@@ -0,0 +1,198 @@
+use std::path::PathBuf;
+
+use futures::future;
+use http::StatusCode;
+use hyper::Body;
+use tokio::prelude::*;
+
+use toshi_types::error::Error;
+use toshi_types::server::{RestoreInfo, SnapshotInfo, SnapshotRequest};
+
+use crate::handlers::ResponseFuture;
+use crate::index::{IndexCatalog, SharedCatalog};
+use crate::snapshot::Repository;
+use crate::utils::{error_response, glob_match, with_body};
+
+fn snapshot_response<T: serde::Serialize>(result: Result<T, Error>) -> http::Response<Body> {
+    match result {
+        Ok(v) => with_body(v),
+        Err(e @ Error::UnknownSnapshot(_)) | Err(e @ Error::UnknownIndex(_)) => error_response(StatusCode::NOT_FOUND, e),
+        Err(e @ Error::IOError(_)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+/// An empty body selects every index.
+fn parse_request(body: &[u8]) -> Result<SnapshotRequest, Error> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(SnapshotRequest::default());
+    }
+    serde_json::from_slice(body).map_err(Into::into)
+}
+
+fn repository(catalog: &SharedCatalog, repo: &str) -> Result<Repository, Error> {
+    let root = PathBuf::from(&catalog.read().settings.snapshot_path);
+    Repository::open(&root, repo)
+}
+
+/// Snapshots the selected local indexes into a repository under the configured snapshot path.
+pub fn create_snapshot(catalog: SharedCatalog, body: Body, repo: String, name: String) -> ResponseFuture {
+    let fut = body.concat2().map(move |b| {
+        let take = || -> Result<SnapshotInfo, Error> {
+            let req = parse_request(&b)?;
+            let repo = repository(&catalog, &repo)?;
+            let (base_path, indexes) = {
+                let cat = catalog.read();
+                let pattern = req.indexes.unwrap_or_else(|| "*".into());
+                let indexes = cat
+                    .resolve_indexes(&pattern)
+                    .iter()
+                    .filter_map(|index| cat.get_owned_index(index).ok())
+                    .collect::<Vec<_>>();
+                if indexes.is_empty() {
+                    return Err(Error::UnknownIndex(pattern));
+                }
+                (cat.base_path().clone(), indexes)
+            };
+            repo.snapshot(&name, &base_path, &indexes)
+        };
+        snapshot_response(take())
+    });
+    Box::new(fut)
+}
+
+pub fn get_snapshot(catalog: SharedCatalog, repo: String, name: String) -> ResponseFuture {
+    let manifest = repository(&catalog, &repo).and_then(|repo| repo.manifest(&name));
+    Box::new(future::ok(snapshot_response(manifest)))
+}
+
+/// Restores the selected indexes of a snapshot, none of which can exist already. The files are
+/// copied before the catalog is locked, so searches and writes elsewhere carry on meanwhile.
+pub fn restore_snapshot(catalog: SharedCatalog, body: Body, repo: String, name: String) -> ResponseFuture {
+    let fut = body.concat2().map(move |b| {
+        let restore = || -> Result<RestoreInfo, Error> {
+            let req = parse_request(&b)?;
+            let repo = repository(&catalog, &repo)?;
+            let manifest = repo.manifest(&name)?;
+            let indexes: Vec<String> = match &req.indexes {
+                Some(patterns) => manifest
+                    .indexes
+                    .keys()
+                    .filter(|index| patterns.split(',').any(|p| glob_match(p.trim(), index)))
+                    .cloned()
+                    .collect(),
+                None => manifest.indexes.keys().cloned().collect(),
+            };
+            if indexes.is_empty() {
+                return Err(Error::UnknownIndex(req.indexes.unwrap_or_default()));
+            }
+            let base_path = {
+                let cat = catalog.read();
+                for index in &indexes {
+                    cat.check_restorable(&manifest, index)?;
+                }
+                cat.base_path().clone()
+            };
+            let staged = IndexCatalog::stage_restore(&base_path, &repo, &manifest, &indexes)?;
+            catalog.write().restore(&manifest, staged)?;
+            Ok(RestoreInfo { indexes })
+        };
+        snapshot_response(restore())
+    });
+    Box::new(fut)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use parking_lot::RwLock;
+    use pretty_assertions::assert_eq;
+
+    use toshi_types::server::{IndexSettings, SnapshotManifest};
+
+    use crate::handlers::search::tests::wait_json;
+    use crate::handlers::{IndexHandler, SearchHandler};
+    use crate::index::IndexCatalog;
+    use crate::settings::Settings;
+    use crate::SearchResults;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let root = std::env::temp_dir().join(format!("toshi-snapshots-{}", uuid::Uuid::new_v4()));
+        let base_path = root.join("data");
+        fs::create_dir_all(&base_path).unwrap();
+        let settings = Settings {
+            snapshot_path: root.join("snapshots").to_string_lossy().to_string(),
+            ..Settings::default()
+        };
+        let catalog = Arc::new(RwLock::new(IndexCatalog::new(base_path.clone(), settings).unwrap()));
+        let schema = toshi_test::create_test_index().schema();
+        catalog.write().create_index("logs", schema, &IndexSettings::default()).unwrap();
+        let handler = IndexHandler::new(Arc::clone(&catalog));
+        let add = |text: &str| {
+            let body = format!(
+                r#"{{ "options": {{ "commit": true }}, "document": {{ "test_text": "{}", "test_u64": 1 }} }}"#,
+                text
+            );
+            handler.add_document(Body::from(body), "logs".into()).wait().unwrap();
+        };
+        let take = |name: &str| {
+            create_snapshot(Arc::clone(&catalog), Body::empty(), "backups".into(), name.into())
+                .wait()
+                .unwrap()
+        };
+
+        add("first");
+        let one: SnapshotInfo = wait_json(take("one"));
+        assert_eq!(one.indexes, vec!["logs".to_string()]);
+        assert_eq!(one.files_reused, 0);
+        assert!(one.files_copied > 0);
+        assert_eq!(take("one").status(), StatusCode::BAD_REQUEST);
+
+        add("second");
+        let two: SnapshotInfo = wait_json(take("two"));
+        assert_eq!(two.files_reused, one.files_copied);
+        assert!(two.files_copied > 0);
+        let manifest: SnapshotManifest = wait_json(get_snapshot(Arc::clone(&catalog), "backups".into(), "two".into()).wait().unwrap());
+        assert_eq!(manifest.indexes["logs"].files.len(), two.files_copied + two.files_reused);
+
+        catalog.write().remove_index("logs").unwrap();
+        let restore = |name: &str| {
+            restore_snapshot(Arc::clone(&catalog), Body::empty(), "backups".into(), name.into())
+                .wait()
+                .unwrap()
+        };
+        let restored: RestoreInfo = wait_json(restore("one"));
+        assert_eq!(restored.indexes, vec!["logs".to_string()]);
+        let search = SearchHandler::new(Arc::clone(&catalog));
+        let all: SearchResults = wait_json(search.all_docs("logs".into()).wait().unwrap());
+        assert_eq!(all.hits, 1);
+        add("third");
+
+        assert_eq!(restore("one").status(), StatusCode::BAD_REQUEST);
+        assert_eq!(restore("three").status(), StatusCode::NOT_FOUND);
+
+        let repo = repository(&catalog, "backups").unwrap();
+        let manifest = repo.manifest("two").unwrap();
+        let staged = IndexCatalog::stage_restore(&base_path, &repo, &manifest, &["logs".to_string()]).unwrap();
+        assert!(staged.iter().all(|(_, path)| path.exists()));
+        assert!(catalog.write().restore(&manifest, staged).is_err());
+        let hidden = fs::read_dir(&base_path)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(".logs"))
+            .count();
+        assert_eq!(hidden, 0);
+        let all: SearchResults = wait_json(search.all_docs("logs".into()).wait().unwrap());
+        assert_eq!(all.hits, 2);
+
+        drop(search);
+        drop(handler);
+        catalog.write().remove_index("logs").unwrap();
+        drop(catalog);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
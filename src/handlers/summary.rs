@@ -60,10 +60,7 @@ pub fn flush(catalog: SharedCatalog, index: String) -> ResponseFuture {
     let fut = future::lazy(move || {
         let index_lock = index_lock.read();
         if index_lock.exists(&index) {
-            let index = index_lock.get_index(&index).unwrap();
-            let writer = index.get_writer();
-            let mut write = writer.write();
-            write.commit().unwrap();
+            index_lock.get_index(&index).unwrap().commit().unwrap();
             future::ok(empty_with_code(StatusCode::OK))
         } else {
             future::ok(empty_with_code(StatusCode::NOT_FOUND))
@@ -1,7 +1,7 @@
 use std::clone::Clone;
 use std::fs;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use futures::stream::Stream;
@@ -11,20 +11,29 @@ use http::Uri;
 use parking_lot::{Mutex, RwLock};
 use tantivy::directory::MmapDirectory;
 use tantivy::schema::Schema;
-use tantivy::Index;
+use tantivy::{Directory, Index, SegmentId};
 use tokio::prelude::*;
+use tower_grpc::Code;
+use tracing::*;
+use uuid::Uuid;
 
 use toshi_proto::cluster_rpc::*;
 use toshi_types::error::Error;
-use toshi_types::query::Search;
+use toshi_types::query::{Query, Search};
 
+use crate::alias::Aliases;
 use crate::cluster::remote_handle::RemoteIndex;
 use crate::cluster::rpc_server::{RpcClient, RpcServer};
 use crate::cluster::RPCError;
 use crate::handle::{IndexHandle, LocalIndex};
+use crate::mapping::infer_schema;
+use crate::scroll::Scrolls;
 use crate::settings::Settings;
+use crate::snapshot::Repository;
+use crate::template::Templates;
+use crate::utils::glob_match;
 use crate::{AddDocument, Result, SearchResults};
-use toshi_types::server::DeleteDoc;
+use toshi_types::server::{AliasAction, DeleteByQuery, DeleteDoc, DocsAffected, IndexSettings, IndexTemplate, SnapshotManifest};
 
 pub type SharedCatalog = Arc<RwLock<IndexCatalog>>;
 
@@ -33,6 +42,9 @@ pub struct IndexCatalog {
     base_path: PathBuf,
     local_handles: HashMap<String, LocalIndex>,
     remote_handles: Arc<Mutex<HashMap<String, RemoteIndex>>>,
+    scrolls: Scrolls,
+    aliases: Aliases,
+    templates: Templates,
 }
 
 impl IndexCatalog {
@@ -44,11 +56,16 @@ impl IndexCatalog {
         let remote_idxs = Arc::new(Mutex::new(HashMap::new()));
         let local_idxs = HashMap::new();
 
+        let aliases = Aliases::load(&base_path)?;
+        let templates = Templates::load(&base_path)?;
         let mut index_cat = IndexCatalog {
             settings,
             base_path,
             local_handles: local_idxs,
             remote_handles: remote_idxs,
+            scrolls: Scrolls::default(),
+            aliases,
+            templates,
         };
         index_cat.refresh_catalog()?;
 
@@ -87,6 +104,9 @@ impl IndexCatalog {
             base_path: PathBuf::new(),
             local_handles: map,
             remote_handles: Arc::new(Mutex::new(remote_map)),
+            scrolls: Scrolls::default(),
+            aliases: Aliases::default(),
+            templates: Templates::default(),
         })
     }
 
@@ -110,12 +130,293 @@ impl IndexCatalog {
         }
     }
 
+    /// Creates a new index under the catalog's base path with Toshi's own settings saved alongside it.
+    pub fn create_index(&mut self, name: &str, schema: Schema, settings: &IndexSettings) -> Result<()> {
+        let index = IndexCatalog::create_from_managed(self.base_path.clone(), name, schema)?;
+        LocalIndex::write_settings(&index, settings)?;
+        self.add_index(name.into(), index)
+    }
+
+    /// Creates an index for a document written to one that doesn't exist yet, starting from the
+    /// schema and settings of the best matching template and inferring every other field from the
+    /// document. Does nothing if the index exists by now.
+    pub fn create_dynamic(&mut self, name: &str, doc: &serde_json::Value) -> Result<()> {
+        if self.exists(name) || self.remote_exists(name) {
+            return Ok(());
+        }
+        if !self.settings.dynamic_mapping {
+            return Err(Error::UnknownIndex(name.into()));
+        }
+        let (base, settings) = match self.templates.matching(name) {
+            Some(template) => (template.schema.as_ref().map(|s| &s.0), template.settings.clone()),
+            None => (None, IndexSettings::default()),
+        };
+        let schema = infer_schema(base, doc, settings.primary_key.as_deref())?;
+        settings.validate(&schema)?;
+        self.create_index(name, schema, &settings)
+    }
+
+    /// The hidden directory a new version of an index is built in before it replaces the index,
+    /// hidden so `refresh_catalog` never loads a half built one.
+    pub fn staging_name(name: &str, schema_version: u64) -> String {
+        format!(".{}.v{}", name, schema_version)
+    }
+
+    /// Builds a copy of `source` with `schema` under `staging`, replacing whatever an earlier attempt
+    /// left there, and returns the `committed_segments` of `source` the copy reflects. A copy that
+    /// fails is removed again, and the error says whether that worked.
+    pub fn stage_index(
+        base_path: &Path,
+        staging: &str,
+        schema: Schema,
+        settings: &IndexSettings,
+        source: &LocalIndex,
+    ) -> Result<Vec<(SegmentId, u32)>> {
+        let path = base_path.join(staging);
+        let copy = || -> Result<Vec<(SegmentId, u32)>> {
+            if path.exists() {
+                fs::remove_dir_all(&path)?;
+            }
+            let index = IndexCatalog::create_from_managed(base_path.to_path_buf(), staging, schema)?;
+            LocalIndex::write_settings(&index, settings)?;
+            source.copy_into(&index)
+        };
+        copy().map_err(|e| {
+            let left = IndexCatalog::discard_staged(&path);
+            Error::IOError(format!(
+                "copying '{}' into a new version failed and {}: {}",
+                source.get_name(),
+                left,
+                e
+            ))
+        })
+    }
+
+    /// Replaces a local index with the one `stage_index` built under `staging` from `copied`. Once the
+    /// index is out of the catalog nothing else can write to it, so if its committed segments no longer
+    /// match the copy, because a write slipped in, it's copied again first. Like any removal this is
+    /// refused while the index is in use. Whenever the swap fails the old version is kept and the
+    /// staged copy removed, the error says if anything was left behind.
+    pub fn swap_index(
+        &mut self,
+        name: &str,
+        staging: &str,
+        schema: Schema,
+        settings: &IndexSettings,
+        copied: &[(SegmentId, u32)],
+    ) -> Result<()> {
+        let staged = self.base_path.join(staging);
+        let handle = match self.take_unshared(name) {
+            Ok(handle) => handle,
+            Err(e) => {
+                IndexCatalog::discard_staged(&staged);
+                return Err(e);
+            }
+        };
+        let current = handle.commit().and_then(|_| {
+            if handle.committed_segments() == copied {
+                return Ok(());
+            }
+            IndexCatalog::stage_index(&self.base_path, staging, schema, settings, &handle).map(|_| ())
+        });
+        if let Err(e) = current {
+            IndexCatalog::discard_staged(&staged);
+            self.local_handles.insert(name.into(), handle);
+            return Err(e);
+        }
+
+        let path = self.base_path.join(name);
+        let retired = self.base_path.join(format!(".{}.retired", name));
+        let swapped = handle.shutdown().and_then(|_| {
+            if retired.exists() {
+                fs::remove_dir_all(&retired)?;
+            }
+            fs::rename(&path, &retired)?;
+            if let Err(e) = fs::rename(&staged, &path) {
+                if let Err(back) = fs::rename(&retired, &path) {
+                    return Err(Error::IOError(format!(
+                        "{}, and the old version is left at {}: {}",
+                        e,
+                        retired.display(),
+                        back
+                    )));
+                }
+                return Err(e.into());
+            }
+            Ok(())
+        });
+        if let Err(e) = swapped {
+            let left = IndexCatalog::discard_staged(&staged);
+            let e = Error::IOError(format!("swapping in the new version of '{}' failed and {}: {}", name, left, e));
+            self.add_index(name.into(), Index::open_in_dir(&path)?)?;
+            return Err(e);
+        }
+        if let Err(e) = fs::remove_dir_all(&retired) {
+            warn!("The old version of {} is left at {}: {}", name, retired.display(), e);
+        }
+        self.add_index(name.into(), Index::open_in_dir(&path)?)
+    }
+
+    /// Removes a staged copy that won't be used, describing what was left behind for error messages.
+    /// One that can't be removed is hidden, so it's never loaded as an index.
+    fn discard_staged(path: &Path) -> String {
+        if !path.exists() {
+            return "nothing was left behind".into();
+        }
+        match fs::remove_dir_all(path) {
+            Ok(()) => "the partial copy was removed".into(),
+            Err(e) => {
+                error!("Error removing staged copy {}: {}", path.display(), e);
+                format!("the partial copy is left at {}", path.display())
+            }
+        }
+    }
+
+    /// Adds fields to a local index without touching its segments by rewriting the schema in its
+    /// `meta.json`, which only works for fields `mapping::needs_reindex` allows. The index is reopened
+    /// with a new writer, so it can't be in use elsewhere, such as by a request in flight.
+    pub fn extend_in_place(&mut self, name: &str, schema: Schema, settings: &IndexSettings) -> Result<()> {
+        let handle = self.take_unshared(name)?;
+        handle.commit()?;
+        let mut meta = handle.get_index().load_metas()?;
+        let mut dir = handle.get_index().directory().clone();
+        handle.shutdown()?;
+        meta.schema = schema;
+        dir.atomic_write(Path::new("meta.json"), &serde_json::to_vec(&meta)?)?;
+        let index = Index::open_in_dir(self.base_path.join(name))?;
+        LocalIndex::write_settings(&index, settings)?;
+        self.add_index(name.into(), index)
+    }
+
+    /// Checks that an index of a snapshot can be restored under its own name, which can't be taken by
+    /// an index or alias already.
+    pub fn check_restorable(&self, manifest: &SnapshotManifest, name: &str) -> Result<()> {
+        if !manifest.indexes.contains_key(name) {
+            return Err(Error::UnknownIndex(name.into()));
+        }
+        if self.exists(name) || self.remote_exists(name) || self.aliases.get(name).is_some() {
+            return Err(Error::QueryError(format!("'{}' already exists, drop it before restoring it", name)));
+        }
+        Ok(())
+    }
+
+    /// Copies indexes out of a snapshot into hidden directories of their own, hidden so
+    /// `refresh_catalog` never loads a half restored one, and returns each name with its directory.
+    /// This is the slow part of a restore so it needs no catalog lock, `restore` then puts them in
+    /// place. Nothing is left behind if any of them fails.
+    pub fn stage_restore(
+        base_path: &Path,
+        repo: &Repository,
+        manifest: &SnapshotManifest,
+        names: &[String],
+    ) -> Result<Vec<(String, PathBuf)>> {
+        let mut staged = Vec::new();
+        let mut stage_all = || -> Result<()> {
+            for name in names {
+                let snapshot = manifest.indexes.get(name).ok_or_else(|| Error::UnknownIndex(name.clone()))?;
+                let path = base_path.join(format!(".{}.restoring-{}", name, Uuid::new_v4()));
+                repo.restore_into(snapshot, &path)?;
+                staged.push((name.clone(), path.clone()));
+                LocalIndex::write_settings(&Index::open_in_dir(&path)?, &snapshot.settings)?;
+            }
+            Ok(())
+        };
+        if let Err(e) = stage_all() {
+            IndexCatalog::remove_staged(&staged);
+            return Err(e);
+        }
+        Ok(staged)
+    }
+
+    /// Moves the indexes staged by `stage_restore` into place and registers them, all or none of them:
+    /// if any name has been taken since, or one fails to open, the ones already restored are removed again.
+    pub fn restore(&mut self, manifest: &SnapshotManifest, staged: Vec<(String, PathBuf)>) -> Result<()> {
+        if let Err(e) = staged.iter().try_for_each(|(name, _)| self.check_restorable(manifest, name)) {
+            IndexCatalog::remove_staged(&staged);
+            return Err(e);
+        }
+        let mut restored = Vec::new();
+        let mut restore_all = || -> Result<()> {
+            for (name, staging) in &staged {
+                let path = self.base_path.join(name);
+                fs::rename(staging, &path)?;
+                restored.push(name.clone());
+                self.add_index(name.clone(), Index::open_in_dir(&path)?)?;
+            }
+            Ok(())
+        };
+        if let Err(e) = restore_all() {
+            for name in &restored {
+                if let Some(handle) = self.local_handles.remove(name) {
+                    if let Err(e) = handle.shutdown() {
+                        error!("Error shutting down restored index {}: {}", name, e);
+                    }
+                }
+                if let Err(e) = fs::remove_dir_all(self.base_path.join(name)) {
+                    error!("Error removing restored index {}: {}", name, e);
+                }
+            }
+            IndexCatalog::remove_staged(&staged);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn remove_staged(staged: &[(String, PathBuf)]) {
+        for (_, path) in staged {
+            IndexCatalog::discard_staged(path);
+        }
+    }
+
     pub fn add_index(&mut self, name: String, index: Index) -> Result<()> {
+        if self.aliases.get(&name).is_some() {
+            return Err(Error::QueryError(format!("index '{}' has the same name as an alias", name)));
+        }
         let handle = LocalIndex::new(index, self.settings.clone(), &name)?;
         self.local_handles.insert(name, handle);
         Ok(())
     }
 
+    /// Takes a local index out of the catalog to replace or delete it, which can't be done while another
+    /// clone of its handle, such as one held by a request in flight, can still write to it. Open scrolls
+    /// only hold a reader and keep reading the version of the index they were opened on.
+    fn take_unshared(&mut self, name: &str) -> Result<LocalIndex> {
+        match self.local_handles.get(name) {
+            Some(handle) if handle.is_shared() => Err(Error::QueryError(format!(
+                "'{}' is in use, try again once its requests are done",
+                name
+            ))),
+            Some(_) => Ok(self.local_handles.remove(name).unwrap()),
+            None => Err(Error::UnknownIndex(name.into())),
+        }
+    }
+
+    /// Removes an index from the catalog, stopping its writer and deleting its directory under
+    /// the catalog's base path. Any remote handle registered under the same name is dropped as well.
+    /// A local index still in use is left in place.
+    pub fn remove_index(&mut self, name: &str) -> Result<()> {
+        let local = if self.local_handles.contains_key(name) {
+            Some(self.take_unshared(name)?)
+        } else {
+            None
+        };
+        let remote = self.remote_handles.lock().remove(name);
+        if local.is_none() && remote.is_none() {
+            return Err(Error::UnknownIndex(name.into()));
+        }
+
+        self.aliases.remove_index(name)?;
+        if let Some(handle) = local {
+            handle.shutdown()?;
+            let mut index_path = self.base_path.clone();
+            index_path.push(name);
+            if index_path.exists() {
+                fs::remove_dir_all(&index_path)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn add_remote_index(&mut self, name: String, remote: RpcClient) -> Result<()> {
         let ri = RemoteIndex::new(name.clone(), remote);
         self.remote_handles.lock().entry(name).or_insert(ri);
@@ -136,6 +437,46 @@ impl IndexCatalog {
         Arc::clone(&self.remote_handles)
     }
 
+    pub fn scrolls(&self) -> &Scrolls {
+        &self.scrolls
+    }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    /// Applies a list of alias actions atomically, aliases can only point to indexes known to the catalog.
+    pub fn update_aliases(&mut self, actions: &[AliasAction]) -> Result<()> {
+        let (local, remote) = (&self.local_handles, self.remote_handles.lock());
+        self.aliases
+            .apply(actions, |name| local.contains_key(name) || remote.contains_key(name))
+    }
+
+    pub fn templates(&self) -> &Templates {
+        &self.templates
+    }
+
+    pub fn put_template(&mut self, name: &str, template: IndexTemplate) -> Result<()> {
+        self.templates.put(name, template)
+    }
+
+    /// Removes a template, returning whether it existed. Indexes already created from it are left as they are.
+    pub fn remove_template(&mut self, name: &str) -> Result<bool> {
+        self.templates.remove(name)
+    }
+
+    /// The index reads and writes of a single document go to, which is the write index when `name` is an alias.
+    /// Every other lookup takes concrete index names, or expands aliases with `resolve_indexes`.
+    pub fn resolve_write_index(&self, name: &str) -> Result<String> {
+        match self.aliases.get(name) {
+            Some(alias) => alias
+                .write_index
+                .clone()
+                .ok_or_else(|| Error::QueryError(format!("alias '{}' has no write index", name))),
+            None => Ok(name.into()),
+        }
+    }
+
     pub fn get_mut_collection(&mut self) -> &mut HashMap<String, LocalIndex> {
         &mut self.local_handles
     }
@@ -148,6 +489,24 @@ impl IndexCatalog {
         self.get_remote_collection().lock().contains_key(index)
     }
 
+    /// Expands a comma separated list of index names, aliases and glob patterns into the names of
+    /// every matching local or remote index. Names that don't match any index are left out.
+    pub fn resolve_indexes(&self, pattern: &str) -> Vec<String> {
+        let mut names: Vec<String> = self.local_handles.keys().cloned().collect();
+        names.extend(self.get_remote_collection().lock().keys().cloned());
+        let mut resolved: Vec<String> = pattern
+            .split(',')
+            .map(str::trim)
+            .flat_map(|part| {
+                let indexes = names.iter().filter(move |name| glob_match(part, name));
+                indexes.chain(self.aliases.matching(part)).cloned()
+            })
+            .collect();
+        resolved.sort();
+        resolved.dedup();
+        resolved
+    }
+
     pub fn get_mut_index(&mut self, name: &str) -> Result<&mut LocalIndex> {
         self.local_handles.get_mut(name).ok_or_else(|| Error::UnknownIndex(name.into()))
     }
@@ -177,7 +536,12 @@ impl IndexCatalog {
         for dir in fs::read_dir(self.base_path.clone())? {
             let entry = dir?.path();
             if let Some(entry_str) = entry.to_str() {
-                if !entry_str.ends_with(".node_id") {
+                let hidden = match entry.file_name().and_then(|n| n.to_str()) {
+                    Some(name) => name.starts_with('.'),
+                    None => false,
+                };
+                // Dotfiles such as .node_id and .aliases.json are Toshi's own, not indexes.
+                if !hidden {
                     let pth: String = entry_str.rsplit('/').take(1).collect();
                     let idx = IndexCatalog::load_index(entry_str)?;
                     self.add_index(pth.clone(), idx)?;
@@ -238,6 +602,43 @@ impl IndexCatalog {
         })
     }
 
+    pub fn count_local_index(&self, index: &str, query: Query) -> impl Future<Item = usize, Error = Error> + Send {
+        self.get_index(index).and_then(move |hand| hand.count(query)).into_future()
+    }
+
+    /// A query the remote node rejects is reported as a `QueryError`, any other failure as an `IOError`.
+    pub fn count_remote_index(&self, index: &str, search: Search) -> impl Future<Item = usize, Error = Error> + Send {
+        self.get_remote_index(index).into_future().and_then(move |hand| {
+            hand.count(search)
+                .map(|replies| replies.iter().map(|r| r.count as usize).sum())
+                .map_err(|status| match status.code() {
+                    Code::InvalidArgument => Error::QueryError(status.message().into()),
+                    _ => Error::IOError(format!("An error occurred with the count: {}", status.message())),
+                })
+        })
+    }
+
+    /// Deletes from a clone of the handle once polled, so the catalog doesn't have to stay locked meanwhile.
+    pub fn delete_local_by_query(&self, index: &str, delete: DeleteByQuery) -> impl Future<Item = u64, Error = Error> + Send {
+        self.get_owned_index(index)
+            .into_future()
+            .and_then(move |hand| future::lazy(move || hand.delete_by_query(delete.query).map(|affected| affected.docs_affected)))
+    }
+
+    pub fn delete_remote_by_query(&self, index: &str, delete: DeleteByQuery) -> impl Future<Item = u64, Error = Error> + Send {
+        self.get_remote_index(index).into_future().and_then(move |hand| {
+            hand.delete_by_query(delete)
+                .map(|replies| {
+                    replies
+                        .iter()
+                        .filter_map(|r| serde_json::from_str::<DocsAffected>(&r.message).ok())
+                        .map(|a| a.docs_affected)
+                        .sum()
+                })
+                .map_err(|_| Error::IOError("An error occurred with the delete".into()))
+        })
+    }
+
     pub fn add_remote_document(&self, index: &str, doc: AddDocument) -> impl Future<Item = (), Error = Error> + Send {
         self.get_remote_index(index)
             .into_future()
@@ -272,12 +673,91 @@ impl IndexCatalog {
 pub mod tests {
 
     use super::*;
+    use crate::mapping::append_fields;
     use parking_lot::RwLock;
     use std::sync::Arc;
+    use tantivy::schema::{Cardinality, FieldEntry, FieldType};
+    use toshi_types::server::IndexSettings;
+
+    /// The test index with `test_i64` and `test_u64` as fast fields, for the tests that sort or
+    /// aggregate on them.
+    pub fn create_sortable_index() -> Index {
+        let source = toshi_test::create_test_index();
+        let fields: Vec<FieldEntry> = source
+            .schema()
+            .fields()
+            .iter()
+            .map(|entry| match entry.field_type() {
+                FieldType::I64(opts) => FieldEntry::new_i64(entry.name().into(), opts.clone().set_fast(Cardinality::SingleValue)),
+                FieldType::U64(opts) => FieldEntry::new_u64(entry.name().into(), opts.clone().set_fast(Cardinality::SingleValue)),
+                _ => entry.clone(),
+            })
+            .collect();
+        let index = Index::create_in_ram(append_fields(&Schema::builder().build(), &fields));
+        let mut writer = index.writer(30_000_000).unwrap();
+        let searcher = source.reader().unwrap().searcher();
+        for segment in searcher.segment_readers() {
+            let store = segment.get_store_reader();
+            for doc in 0..segment.max_doc() {
+                writer.add_document(store.get(doc).unwrap());
+            }
+        }
+        writer.commit().unwrap();
+        index
+    }
+
+    pub fn create_sortable_catalog(name: &str) -> SharedCatalog {
+        let catalog = IndexCatalog::with_index(name.into(), create_sortable_index()).unwrap();
+        Arc::new(RwLock::new(catalog))
+    }
 
     pub fn create_test_catalog(name: &str) -> SharedCatalog {
         let idx = toshi_test::create_test_index();
         let catalog = IndexCatalog::with_index(name.into(), idx).unwrap();
         Arc::new(RwLock::new(catalog))
     }
+
+    pub fn create_test_catalog_with_key(name: &str, key: &str) -> SharedCatalog {
+        let idx = toshi_test::create_test_index();
+        LocalIndex::write_settings(&idx, &IndexSettings::with_primary_key(key)).unwrap();
+        let catalog = IndexCatalog::with_index(name.into(), idx).unwrap();
+        Arc::new(RwLock::new(catalog))
+    }
+
+    #[test]
+    fn test_aliases_survive_restart() {
+        let base_path = std::env::temp_dir().join(format!("toshi-aliases-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&base_path).unwrap();
+        {
+            let mut cat = IndexCatalog::with_path(base_path.clone()).unwrap();
+            let schema = toshi_test::create_test_index().schema();
+            let index = IndexCatalog::create_from_managed(base_path.clone(), "products-1", schema).unwrap();
+            cat.add_index("products-1".into(), index).unwrap();
+            let add = AliasAction::Add {
+                index: "products-1".into(),
+                alias: "products".into(),
+                is_write_index: false,
+            };
+            cat.update_aliases(&[add]).unwrap();
+        }
+
+        let cat = IndexCatalog::with_path(base_path.clone()).unwrap();
+        assert_eq!(cat.get_collection().len(), 1);
+        assert_eq!(cat.resolve_indexes("products"), vec!["products-1".to_string()]);
+        assert_eq!(cat.resolve_write_index("products").unwrap(), "products-1");
+        assert!(cat.get_index("products").is_err());
+        drop(cat);
+        fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_shared_index() {
+        let cat = create_test_catalog("shared_index");
+        let in_flight = cat.read().get_owned_index("shared_index").unwrap();
+        assert!(cat.write().remove_index("shared_index").is_err());
+        assert!(cat.read().exists("shared_index"));
+        drop(in_flight);
+        cat.write().remove_index("shared_index").unwrap();
+        assert!(!cat.read().exists("shared_index"));
+    }
 }
@@ -7,18 +7,27 @@ use tantivy::schema::Value;
 
 use toshi_types::client::SearchResults as SD;
 use toshi_types::server::AddDocument as AD;
+use toshi_types::server::UpdateDocument as UD;
 
+pub mod alias;
 pub mod cluster;
+pub mod collectors;
 pub mod commit;
 pub mod handle;
 pub mod handlers;
 pub mod index;
+pub mod mapping;
 pub mod router;
+pub mod scroll;
 pub mod settings;
 pub mod shutdown;
+pub mod snapshot;
 pub mod support;
+pub mod template;
 pub mod utils;
 
 pub type Result<T> = std::result::Result<T, toshi_types::error::Error>;
 pub type AddDocument = AD<serde_json::Value>;
-pub type SearchResults = SD<BTreeMap<String, Vec<Value>>>;
+pub type UpdateDocument = UD<serde_json::Value>;
+pub type NamedDoc = BTreeMap<String, Vec<Value>>;
+pub type SearchResults = SD<NamedDoc>;
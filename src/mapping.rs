@@ -0,0 +1,250 @@
+use serde_json::{Map, Value as JsonValue};
+use tantivy::chrono::{DateTime, TimeZone, Utc};
+use tantivy::schema::*;
+use tantivy::Document;
+
+use toshi_types::error::Error;
+
+use crate::Result;
+
+/// Builds the schema of an index created by writing `doc` to it. Fields in `base` keep their
+/// definition and every other field of the document gets one inferred from its value:
+///
+/// * strings are dates if they are RFC 3339 timestamps and text otherwise, the primary key uses
+///   the raw tokenizer so it is indexed as a single term
+/// * whole numbers are i64, or u64 if they don't fit in one, other numbers are f64
+/// * arrays take the type of their first element
+///
+/// Numbers are indexed, stored and single valued fast fields so they can be sorted and aggregated
+/// on, the fast value of an array of numbers is its first element. Fields are only inferred when the
+/// index is created, a document with a field the index doesn't have is rejected like on any other
+/// index until the field is added to the schema.
+pub fn infer_schema(base: Option<&Schema>, doc: &JsonValue, primary_key: Option<&str>) -> Result<Schema> {
+    let doc = doc
+        .as_object()
+        .ok_or_else(|| Error::QueryError("a document has to be a JSON object".into()))?;
+    let empty = Schema::builder().build();
+    let base = base.unwrap_or(&empty);
+    let inferred = doc
+        .iter()
+        .filter(|(name, _)| base.get_field(name).is_none())
+        .map(|(name, value)| infer_field(name, value, primary_key == Some(name.as_str())))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(append_fields(base, &inferred))
+}
+
+fn infer_field(name: &str, value: &JsonValue, primary_key: bool) -> Result<FieldEntry> {
+    let value = match value {
+        JsonValue::Array(values) => values.first().unwrap_or(&JsonValue::Null),
+        value => value,
+    };
+    let numeric = IntOptions::default().set_indexed().set_stored().set_fast(Cardinality::SingleValue);
+    let entry = match value {
+        JsonValue::String(s) if parse_date(s).is_some() => {
+            FieldEntry::new_date(name.into(), IntOptions::default().set_indexed().set_stored())
+        }
+        JsonValue::String(_) if primary_key => FieldEntry::new_text(name.into(), STRING | STORED),
+        JsonValue::String(_) => FieldEntry::new_text(name.into(), TEXT | STORED),
+        JsonValue::Number(n) if n.is_i64() => FieldEntry::new_i64(name.into(), numeric),
+        JsonValue::Number(n) if n.is_u64() => FieldEntry::new_u64(name.into(), numeric),
+        JsonValue::Number(_) => FieldEntry::new_f64(name.into(), numeric),
+        other => {
+            return Err(Error::QueryError(format!(
+                "can't infer the type of field '{}' from {}, it has to be a string or a number",
+                name, other
+            )))
+        }
+    };
+    Ok(entry)
+}
+
+/// A copy of `schema` with `fields` added after its own, so the fields it already has keep their ids.
+pub fn append_fields(schema: &Schema, fields: &[FieldEntry]) -> Schema {
+    let mut builder = Schema::builder();
+    for entry in schema.fields().iter().chain(fields) {
+        add_field(&mut builder, entry);
+    }
+    builder.build()
+}
+
+/// Whether adding `entry` to an index means copying its documents into a new one. Segments written
+/// before the field existed have no field norms or fast field data for it, which tantivy expects of
+/// every indexed or fast field, so only fields that are just stored can be added to them.
+pub fn needs_reindex(entry: &FieldEntry) -> bool {
+    match entry.field_type() {
+        FieldType::U64(opts) | FieldType::I64(opts) | FieldType::F64(opts) | FieldType::Date(opts) => opts.is_indexed() || opts.is_fast(),
+        FieldType::Str(_) => entry.is_indexed(),
+        FieldType::HierarchicalFacet | FieldType::Bytes => true,
+    }
+}
+
+fn add_field(builder: &mut SchemaBuilder, entry: &FieldEntry) {
+    let name = entry.name();
+    match entry.field_type().clone() {
+        FieldType::Str(opts) => builder.add_text_field(name, opts),
+        FieldType::U64(opts) => builder.add_u64_field(name, opts),
+        FieldType::I64(opts) => builder.add_i64_field(name, opts),
+        FieldType::F64(opts) => builder.add_f64_field(name, opts),
+        FieldType::Date(opts) => builder.add_date_field(name, opts),
+        FieldType::HierarchicalFacet => builder.add_facet_field(name),
+        FieldType::Bytes => builder.add_bytes_field(name),
+    };
+}
+
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|d| d.with_timezone(&Utc))
+}
+
+/// Parses a JSON document against `schema` the way `Schema::parse_document` does, except that date
+/// fields take RFC 3339 strings as well as timestamps in seconds. This applies to every index, not
+/// just dynamically created ones, `Schema::parse_document` turns a timestamp into an i64 value that
+/// makes indexing an indexed date field panic, and rejects strings.
+pub fn parse_document(schema: &Schema, json: &str) -> Result<Document> {
+    let object: Map<String, JsonValue> = serde_json::from_str(json).map_err(|_| {
+        let sample = if json.len() < 20 {
+            json.to_string()
+        } else {
+            format!("{:?}...", &json[0..20])
+        };
+        DocParsingError::NotJSON(sample)
+    })?;
+    let mut doc = Document::default();
+    for (name, value) in &object {
+        let field = schema.get_field(name).ok_or_else(|| Error::UnknownIndexField(name.clone()))?;
+        let field_type = schema.get_field_entry(field).field_type();
+        let values = match value {
+            JsonValue::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let parsed = match (field_type, value) {
+                (FieldType::Date(_), JsonValue::String(s)) => parse_date(s).map(Value::Date),
+                (FieldType::Date(_), JsonValue::Number(n)) => n.as_i64().map(|t| Value::Date(Utc.timestamp(t, 0))),
+                _ => Some(
+                    field_type
+                        .value_from_json(value)
+                        .map_err(|e| DocParsingError::ValueError(name.clone(), e))?,
+                ),
+            };
+            let value = parsed.ok_or_else(|| Error::QueryError(format!("'{}' in field '{}' is not a valid date", value, name)))?;
+            doc.add(FieldValue::new(field, value));
+        }
+    }
+    Ok(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_infer_schema() {
+        let doc = serde_json::json!({
+            "id": "a-1",
+            "message": "disk full",
+            "@timestamp": "2026-10-18T12:00:00Z",
+            "level": 3,
+            "bytes": 18_446_744_073_709_551_615u64,
+            "ratio": 0.5,
+            "tags": ["disk", "alert"]
+        });
+        let schema = infer_schema(None, &doc, Some("id")).unwrap();
+        let type_of = |name: &str| schema.get_field_entry(schema.get_field(name).unwrap()).field_type().value_type();
+        assert_eq!(type_of("@timestamp"), Type::Date);
+        assert_eq!(type_of("message"), Type::Str);
+        assert_eq!(type_of("tags"), Type::Str);
+        assert_eq!(type_of("level"), Type::I64);
+        assert_eq!(type_of("bytes"), Type::U64);
+        assert_eq!(type_of("ratio"), Type::F64);
+
+        let key = schema.get_field_entry(schema.get_field("id").unwrap());
+        match key.field_type() {
+            FieldType::Str(opts) => assert_eq!(opts.get_indexing_options().unwrap().tokenizer(), "raw"),
+            other => panic!("unexpected key type {:?}", other),
+        }
+
+        let parsed = parse_document(&schema, &doc.to_string()).unwrap();
+        let timestamp = parsed.get_first(schema.get_field("@timestamp").unwrap()).unwrap();
+        assert_eq!(timestamp, &Value::Date(parse_date("2026-10-18T12:00:00Z").unwrap()));
+        assert_eq!(parsed.get_all(schema.get_field("tags").unwrap()).len(), 2);
+
+        let levels = infer_schema(None, &serde_json::json!({ "levels": [3, 1] }), None).unwrap();
+        match levels.get_field_entry(levels.get_field("levels").unwrap()).field_type() {
+            FieldType::I64(opts) => assert_eq!(opts.get_fastfield_cardinality(), Some(Cardinality::SingleValue)),
+            other => panic!("unexpected type {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_like_tantivy() {
+        let mut builder = Schema::builder();
+        builder.add_text_field("title", TEXT | STORED);
+        builder.add_u64_field("count", INDEXED | STORED);
+        builder.add_i64_field("delta", FAST);
+        builder.add_facet_field("path");
+        let schema = builder.build();
+        let docs = [
+            r#"{ "title": ["one", "two"], "count": 3, "delta": -4, "path": "/a/b" }"#,
+            r#"{ "title": "one", "missing": 1 }"#,
+            r#"{ "count": -3 }"#,
+            r#"{ "count": "three" }"#,
+            r#"{ "title": 3 }"#,
+            r#"{ "title": "not closed" "#,
+            r#"not json"#,
+        ];
+        for doc in docs.iter() {
+            let expected = schema.parse_document(doc).map_err(Error::from);
+            match (parse_document(&schema, doc), expected) {
+                (Ok(parsed), Ok(expected)) => assert_eq!(schema.to_json(&parsed), schema.to_json(&expected)),
+                (Err(e), Err(expected)) => assert_eq!(e.to_string(), expected.to_string()),
+                (parsed, expected) => panic!("{} parsed to {:?}, tantivy gives {:?}", doc, parsed, expected),
+            }
+        }
+
+        let mut builder = Schema::builder();
+        let at = builder.add_date_field("at", INDEXED | STORED);
+        let schema = builder.build();
+        let date = Value::Date(parse_date("2026-10-18T12:00:00Z").unwrap());
+        let parsed = parse_document(&schema, r#"{ "at": "2026-10-18T12:00:00Z" }"#).unwrap();
+        assert_eq!(parsed.get_first(at), Some(&date));
+        let parsed = parse_document(&schema, &format!(r#"{{ "at": {} }}"#, date.date_value().timestamp())).unwrap();
+        assert_eq!(parsed.get_first(at), Some(&date));
+        assert!(parse_document(&schema, r#"{ "at": "yesterday" }"#).is_err());
+    }
+
+    #[test]
+    fn test_infer_with_base() {
+        let mut builder = Schema::builder();
+        builder.add_text_field("level", STRING | STORED);
+        let base = builder.build();
+        let doc = serde_json::json!({ "level": 3, "host": "web-1" });
+        let schema = infer_schema(Some(&base), &doc, None).unwrap();
+        assert_eq!(schema.fields().len(), 2);
+        assert_eq!(
+            schema.get_field_entry(schema.get_field("level").unwrap()).field_type().value_type(),
+            Type::Str
+        );
+
+        assert!(infer_schema(None, &serde_json::json!({ "ok": true }), None).is_err());
+        assert!(infer_schema(None, &serde_json::json!({ "empty": [] }), None).is_err());
+        assert!(infer_schema(None, &serde_json::json!([1, 2]), None).is_err());
+    }
+
+    #[test]
+    fn test_needs_reindex() {
+        let stored = FieldEntry::new_text("note".into(), STORED.into());
+        assert!(!needs_reindex(&stored));
+        assert!(!needs_reindex(&FieldEntry::new_u64("count".into(), STORED.into())));
+        assert!(needs_reindex(&FieldEntry::new_text("title".into(), TEXT)));
+        assert!(needs_reindex(&FieldEntry::new_u64("rank".into(), FAST.into())));
+        assert!(needs_reindex(&FieldEntry::new_facet("path".into())));
+
+        let mut builder = Schema::builder();
+        builder.add_text_field("title", TEXT);
+        let schema = append_fields(&builder.build(), &[stored]);
+        assert_eq!(schema.get_field("title"), Some(Field(0)));
+        assert_eq!(schema.get_field("note"), Some(Field(1)));
+    }
+}
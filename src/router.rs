@@ -7,6 +7,12 @@ use hyper::{Body, Method, Request, Server};
 use serde::Deserialize;
 use tokio::prelude::*;
 
+use toshi_types::server::IndexSettings;
+
+use crate::handlers::catalog::{
+    cat_indexes, delete_template, get_template, list_aliases, list_indexes, list_templates, put_template, update_aliases,
+};
+use crate::handlers::snapshot::{create_snapshot, get_snapshot, restore_snapshot};
 use crate::handlers::summary::flush;
 use crate::handlers::*;
 use crate::index::SharedCatalog;
@@ -16,6 +22,8 @@ use crate::utils::{not_found, parse_path};
 pub struct QueryOptions {
     pub pretty: Option<bool>,
     pub include_sizes: Option<bool>,
+    pub primary_key: Option<String>,
+    pub scroll: Option<String>,
 }
 
 impl QueryOptions {
@@ -58,20 +66,65 @@ pub fn router_with_catalog(
             tracing::info!("REQ = {:?}", path);
 
             match (&method, &path[..]) {
+                (m, ["_indexes"]) if m == Method::GET => list_indexes(Arc::clone(summary_cat)),
+                (m, ["_cat", "indexes"]) if m == Method::GET => cat_indexes(Arc::clone(summary_cat)),
+                (m, ["_aliases"]) if m == Method::GET => list_aliases(Arc::clone(summary_cat)),
+                (m, ["_aliases"]) if m == Method::POST => update_aliases(Arc::clone(summary_cat), body),
+                (m, ["_template"]) if m == Method::GET => list_templates(Arc::clone(summary_cat)),
+                (m, ["_template", name]) if m == Method::GET => get_template(Arc::clone(summary_cat), (*name).to_string()),
+                (m, ["_template", name]) if m == Method::PUT => put_template(Arc::clone(summary_cat), body, (*name).to_string()),
+                (m, ["_template", name]) if m == Method::DELETE => delete_template(Arc::clone(summary_cat), (*name).to_string()),
+                (m, ["_snapshot", repo, name]) if m == Method::PUT => {
+                    create_snapshot(Arc::clone(summary_cat), body, (*repo).to_string(), (*name).to_string())
+                }
+                (m, ["_snapshot", repo, name]) if m == Method::GET => {
+                    get_snapshot(Arc::clone(summary_cat), (*repo).to_string(), (*name).to_string())
+                }
+                (m, ["_snapshot", repo, name, "_restore"]) if m == Method::POST => {
+                    restore_snapshot(Arc::clone(summary_cat), body, (*repo).to_string(), (*name).to_string())
+                }
+                (m, ["_reindex"]) if m == Method::POST => index_handler.reindex(body),
+                (m, ["_msearch"]) if m == Method::POST => search_handler.multi_search(body),
+                (m, ["_scroll"]) if m == Method::POST => search_handler.scroll(body),
+                (m, ["_scroll"]) if m == Method::DELETE => search_handler.clear_scroll(body),
                 (m, [idx, action]) if m == Method::PUT => match *action {
-                    "_create" => index_handler.create_index(body, (*idx).to_string()),
+                    "_schema" => index_handler.add_fields(body, (*idx).to_string()),
+                    "_create" => {
+                        let settings = IndexSettings {
+                            primary_key: query_options.primary_key,
+                            ..IndexSettings::default()
+                        };
+                        index_handler.create_index(body, (*idx).to_string(), settings)
+                    }
                     _ => not_found(),
                 },
                 (m, [idx, action]) if m == Method::GET => match *action {
                     "_summary" => summary(Arc::clone(summary_cat), (*idx).to_string(), query_options),
                     "_flush" => flush(Arc::clone(summary_cat), (*idx).to_string()),
+                    "_schema" => index_handler.get_schema((*idx).to_string()),
                     _ => not_found(),
                 },
                 (m, [idx, action]) if m == Method::POST => match *action {
                     "_bulk" => bulk_handler.bulk_insert(body, (*idx).to_string()),
+                    "_mget" => index_handler.multi_get(body, (*idx).to_string()),
+                    "_count" => search_handler.count(body, (*idx).to_string()),
+                    "_delete_by_query" => index_handler.delete_by_query(body, (*idx).to_string()),
+                    "_update_by_query" => index_handler.update_by_query(body, (*idx).to_string()),
                     _ => not_found(),
                 },
-                (m, [idx]) if m == Method::POST => search_handler.doc_search(body, (*idx).to_string()),
+                (m, [idx, action]) if m == Method::DELETE => match *action {
+                    "_drop" => index_handler.drop_index((*idx).to_string()),
+                    _ => not_found(),
+                },
+                (m, [idx, "_doc", id]) if m == Method::GET => index_handler.get_document((*idx).to_string(), (*id).to_string()),
+                (m, [idx, "_update", id]) if m == Method::POST => {
+                    index_handler.update_document(body, (*idx).to_string(), (*id).to_string())
+                }
+                (m, [idx, "_explain", id]) if m == Method::POST => search_handler.explain(body, (*idx).to_string(), (*id).to_string()),
+                (m, [idx]) if m == Method::POST => match query_options.scroll {
+                    Some(keep_alive) => search_handler.open_scroll(body, (*idx).to_string(), keep_alive),
+                    None => search_handler.doc_search(body, (*idx).to_string()),
+                },
                 (m, [idx]) if m == Method::PUT => index_handler.add_document(body, (*idx).to_string()),
                 (m, [idx]) if m == Method::DELETE => index_handler.delete_term(body, (*idx).to_string()),
                 (m, [idx]) if m == Method::GET => {
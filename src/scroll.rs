@@ -0,0 +1,321 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use tantivy::{DocAddress, IndexReader};
+use tokio::timer::Interval;
+use tracing::*;
+use uuid::Uuid;
+
+use toshi_types::error::Error;
+use toshi_types::query::Search;
+
+use crate::handle::{IndexHandle, LocalIndex};
+use crate::index::SharedCatalog;
+use crate::{Result, SearchResults};
+
+/// How often, in seconds, expired scrolls are looked for.
+pub const REAP_INTERVAL: u64 = 10;
+/// The longest a scroll can be kept alive between calls, in seconds.
+pub const MAX_KEEP_ALIVE: u64 = 86_400;
+
+/// A search being read in batches from a snapshot of one index, taken when the scroll was opened.
+/// Only the snapshot's reader is kept, not the index's handle, so an open scroll doesn't keep the
+/// index from being dropped or replaced.
+struct ScrollContext {
+    index: String,
+    reader: IndexReader,
+    search: Search,
+    /// Where the next batch of an unsorted scroll starts, see `LocalIndex::scan`. Sorted scrolls
+    /// rerun the search past the last hit instead.
+    position: Option<DocAddress>,
+    total_hits: usize,
+    keep_alive: Duration,
+    expires: Instant,
+    done: bool,
+}
+
+impl ScrollContext {
+    /// Reads the next batch and moves the context past it. Aggregations, facets and `from` only
+    /// apply to the first batch.
+    fn next_batch(&mut self) -> Result<SearchResults> {
+        if self.done {
+            return Ok(SearchResults::new(Vec::new()));
+        }
+        let searcher = self.reader.searcher();
+        let results = match self.position.as_mut() {
+            Some(position) => {
+                SearchResults::new(LocalIndex::scan(&self.index, &searcher, &self.search, position)?).with_total(self.total_hits)
+            }
+            None => LocalIndex::search_with(&self.index, &searcher, self.search.clone())?,
+        };
+        self.done = results.docs.len() < self.search.limit;
+        self.search.search_after = results.docs.last().and_then(|d| d.cursor.clone());
+        self.search.from = 0;
+        Ok(results)
+    }
+
+    /// The first batch, which is the only one that counts every hit and runs the aggregations and
+    /// facets. An unsorted scroll reads its hits in index order, so they're scanned separately.
+    fn first_batch(&mut self) -> Result<SearchResults> {
+        if self.position.is_none() {
+            let results = self.next_batch()?;
+            self.search.aggs.clear();
+            self.search.facets = None;
+            return Ok(results);
+        }
+        let counts = Search {
+            from: 0,
+            limit: 0,
+            ..self.search.clone()
+        };
+        let totals = LocalIndex::search_with(&self.index, &self.reader.searcher(), counts)?;
+        self.total_hits = totals.total_hits;
+        let results = self.next_batch()?;
+        Ok(SearchResults {
+            hits: results.hits,
+            docs: results.docs,
+            ..totals
+        })
+    }
+}
+
+/// The open scrolls of a catalog, each one keeps its snapshot alive until it is cleared or goes
+/// unused for longer than its keep alive.
+#[derive(Clone, Default)]
+pub struct Scrolls {
+    contexts: Arc<Mutex<HashMap<String, Arc<Mutex<ScrollContext>>>>>,
+}
+
+impl Scrolls {
+    /// Pins the index's current segments, returns the first batch of `search` and the scroll id
+    /// to fetch the rest with. Each batch holds up to `search.limit` hits. Without a sort the hits
+    /// come in index order rather than by score, which lets each batch carry on where the last one
+    /// stopped instead of searching the whole snapshot again.
+    pub fn open(&self, index: &LocalIndex, search: Search, keep_alive: Duration) -> Result<SearchResults> {
+        let position = if search.sort.is_empty() { Some(DocAddress(0, 0)) } else { None };
+        let mut context = ScrollContext {
+            index: index.get_name(),
+            reader: index.snapshot()?,
+            search,
+            position,
+            total_hits: 0,
+            keep_alive,
+            expires: Instant::now() + keep_alive,
+            done: false,
+        };
+        let results = context.first_batch()?;
+        let id = Uuid::new_v4().to_simple().to_string();
+        self.contexts.lock().insert(id.clone(), Arc::new(Mutex::new(context)));
+        Ok(results.with_scroll_id(id))
+    }
+
+    /// Returns the next batch of a scroll, an exhausted scroll keeps returning no hits until it
+    /// is cleared or expires. `keep_alive` replaces the scroll's keep alive from now on.
+    pub fn next(&self, id: &str, keep_alive: Option<Duration>) -> Result<SearchResults> {
+        let context = self
+            .contexts
+            .lock()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Error::UnknownScroll(id.into()))?;
+        let mut context = context.lock();
+        if let Some(keep_alive) = keep_alive {
+            context.keep_alive = keep_alive;
+        }
+        context.expires = Instant::now() + context.keep_alive;
+        let results = context.next_batch()?;
+        Ok(results.with_scroll_id(id.into()))
+    }
+
+    /// Releases a scroll's snapshot, returning whether the scroll existed.
+    pub fn clear(&self, id: &str) -> bool {
+        self.contexts.lock().remove(id).is_some()
+    }
+
+    /// Removes every scroll that has gone unused for longer than its keep alive, returning how many there were.
+    pub fn reap(&self) -> usize {
+        let now = Instant::now();
+        let mut contexts = self.contexts.lock();
+        let before = contexts.len();
+        contexts.retain(|_, context| match context.try_lock() {
+            Some(context) => context.expires > now,
+            // Still being read, so it can't have expired.
+            None => true,
+        });
+        before - contexts.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.contexts.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Parses a keep alive such as `30s`, `5m`, `1h` or `1d`, which can't be longer than `MAX_KEEP_ALIVE`.
+pub fn parse_keep_alive(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let bad_value = || Error::QueryError(format!("'{}' is not a valid scroll keep alive", value));
+    let split = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(bad_value)?;
+    let amount = value[..split].parse::<u64>().map_err(|_| bad_value())?;
+    let unit = match &value[split..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return Err(bad_value()),
+    };
+    match amount.checked_mul(unit) {
+        Some(seconds) if seconds <= MAX_KEEP_ALIVE => Ok(Duration::from_secs(seconds)),
+        _ => Err(Error::QueryError(format!(
+            "scroll keep alive '{}' is longer than the maximum of {}s",
+            value, MAX_KEEP_ALIVE
+        ))),
+    }
+}
+
+/// Reaps expired scrolls from the catalog every `interval` seconds.
+pub fn reaper(cat: SharedCatalog, interval: u64) -> impl Future<Item = (), Error = ()> + Send {
+    Interval::new_interval(Duration::from_secs(interval))
+        .for_each(move |_| {
+            let reaped = cat.read().scrolls().reap();
+            if reaped > 0 {
+                debug!("Reaped {} expired scrolls", reaped);
+            }
+            Ok(())
+        })
+        .map_err(|e| panic!("Error in scroll-reaper={:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use pretty_assertions::assert_eq;
+
+    use toshi_types::query::Query;
+    use toshi_types::server::{DeleteDoc, IndexOptions};
+
+    use crate::index::tests::create_test_catalog;
+    use crate::AddDocument;
+
+    use super::*;
+
+    #[test]
+    fn test_scroll_snapshot() {
+        let cat = create_test_catalog("test_index");
+        let index = cat.read().get_owned_index("test_index").unwrap();
+        let scrolls = Scrolls::default();
+        let search = Search::new(Some(Query::All), None, 2);
+
+        let first = scrolls.open(&index, search, Duration::from_secs(60)).unwrap();
+        let id = first.scroll_id.clone().unwrap();
+        assert_eq!((first.hits, first.total_hits), (2, 5));
+
+        let mut terms = std::collections::HashMap::new();
+        terms.insert("test_text".to_string(), "document".to_string());
+        let delete = DeleteDoc {
+            options: Some(IndexOptions { commit: true }),
+            terms,
+        };
+        index.delete_term(delete).unwrap();
+        assert_eq!(index.get_reader().searcher().num_docs(), 2);
+
+        let mut seen = first.docs.len();
+        loop {
+            let batch = scrolls.next(&id, None).unwrap();
+            assert_eq!(batch.scroll_id.as_deref(), Some(id.as_str()));
+            if batch.docs.is_empty() {
+                break;
+            }
+            seen += batch.docs.len();
+        }
+        assert_eq!(seen, 5);
+        assert!(scrolls.next(&id, None).unwrap().docs.is_empty());
+        assert!(scrolls.clear(&id));
+        assert!(scrolls.next(&id, None).is_err());
+    }
+
+    #[test]
+    fn test_scroll_in_index_order() {
+        let cat = create_test_catalog("test_index");
+        let index = cat.read().get_owned_index("test_index").unwrap();
+        let doc: AddDocument =
+            serde_json::from_str(r#"{ "options": { "commit": true }, "document": { "test_text": "Another Document" } }"#).unwrap();
+        index.add_document(doc).unwrap();
+        assert_eq!(index.get_reader().searcher().segment_readers().len(), 2);
+
+        let scrolls = Scrolls::default();
+        let term = serde_json::from_str(r#"{ "query": { "term": { "test_text": "document" } }, "limit": 1, "from": 1 }"#).unwrap();
+        let first = scrolls.open(&index, term, Duration::from_secs(60)).unwrap();
+        let id = first.scroll_id.clone().unwrap();
+        assert_eq!(first.total_hits, 4);
+        let mut seen: Vec<(u32, u32)> = first
+            .docs
+            .iter()
+            .flat_map(|d| d.cursor.as_ref())
+            .map(|c| (c.segment, c.doc))
+            .collect();
+        loop {
+            let batch = scrolls.next(&id, None).unwrap();
+            if batch.docs.is_empty() {
+                break;
+            }
+            assert_eq!(batch.total_hits, 4);
+            seen.extend(batch.docs.iter().flat_map(|d| d.cursor.as_ref()).map(|c| (c.segment, c.doc)));
+        }
+        assert_eq!(seen.len(), 3);
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_scroll_outlives_index() {
+        let cat = create_test_catalog("test_index");
+        let index = cat.read().get_owned_index("test_index").unwrap();
+        let scrolls = Scrolls::default();
+        let first = scrolls
+            .open(&index, Search::new(Some(Query::All), None, 2), Duration::from_secs(60))
+            .unwrap();
+        drop(index);
+
+        cat.write().remove_index("test_index").unwrap();
+        let next = scrolls.next(&first.scroll_id.unwrap(), None).unwrap();
+        assert_eq!((next.hits, next.total_hits), (2, 5));
+    }
+
+    #[test]
+    fn test_scroll_expiry() {
+        let cat = create_test_catalog("test_index");
+        let index = cat.read().get_owned_index("test_index").unwrap();
+        let scrolls = Scrolls::default();
+        let short = scrolls
+            .open(&index, Search::new(Some(Query::All), None, 1), Duration::from_millis(10))
+            .unwrap();
+        let long = scrolls
+            .open(&index, Search::new(Some(Query::All), None, 1), Duration::from_secs(60))
+            .unwrap();
+        sleep(Duration::from_millis(50));
+        assert_eq!(scrolls.reap(), 1);
+        assert_eq!(scrolls.len(), 1);
+        assert!(scrolls.next(&short.scroll_id.unwrap(), None).is_err());
+        assert!(scrolls.next(&long.scroll_id.unwrap(), None).is_ok());
+    }
+
+    #[test]
+    fn test_parse_keep_alive() {
+        assert_eq!(parse_keep_alive("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_keep_alive("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_keep_alive("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_keep_alive("m").is_err());
+        assert!(parse_keep_alive("10").is_err());
+        assert!(parse_keep_alive("10w").is_err());
+        assert_eq!(parse_keep_alive("1d").unwrap(), Duration::from_secs(MAX_KEEP_ALIVE));
+        assert!(parse_keep_alive("25h").is_err());
+        assert!(parse_keep_alive("99999999999999d").is_err());
+    }
+}
@@ -81,6 +81,9 @@ pub struct Settings {
     pub path: String,
     #[serde(default = "Settings::default_place_addr")]
     pub place_addr: String,
+    /// Where snapshot repositories are kept, each repository is a directory under it.
+    #[serde(default = "Settings::default_snapshot_path")]
+    pub snapshot_path: String,
     #[serde(default = "Settings::default_level")]
     pub log_level: String,
     #[serde(default = "Settings::default_writer_memory")]
@@ -93,6 +96,9 @@ pub struct Settings {
     pub bulk_buffer_size: usize,
     #[serde(default = "Settings::default_merge_policy")]
     pub merge_policy: ConfigMergePolicy,
+    /// Whether writing to an index that doesn't exist creates it, see `mapping::infer_schema`. Off by default.
+    #[serde(default = "Settings::default_dynamic_mapping")]
+    pub dynamic_mapping: bool,
     #[serde(default = "Settings::default_experimental")]
     pub experimental: bool,
     #[serde(default = "Experimental::default")]
@@ -106,12 +112,14 @@ impl Default for Settings {
             port: Settings::default_port(),
             path: Settings::default_path(),
             place_addr: Settings::default_place_addr(),
+            snapshot_path: Settings::default_snapshot_path(),
             log_level: Settings::default_level(),
             writer_memory: Settings::default_writer_memory(),
             json_parsing_threads: Settings::default_json_parsing_threads(),
             auto_commit_duration: Settings::default_auto_commit_duration(),
             bulk_buffer_size: Settings::default_bulk_buffer_size(),
             merge_policy: Settings::default_merge_policy(),
+            dynamic_mapping: Settings::default_dynamic_mapping(),
             experimental: Settings::default_experimental(),
             experimental_features: Experimental::default(),
         }
@@ -182,6 +190,10 @@ impl Settings {
         "0.0.0.0:8082".to_string()
     }
 
+    pub fn default_snapshot_path() -> String {
+        "snapshots/".to_string()
+    }
+
     pub fn default_level() -> String {
         "info".to_string()
     }
@@ -227,6 +239,10 @@ impl Settings {
         Vec::new()
     }
 
+    pub fn default_dynamic_mapping() -> bool {
+        false
+    }
+
     pub fn default_experimental() -> bool {
         false
     }
@@ -273,6 +289,7 @@ mod tests {
         assert_eq!(default.host, "0.0.0.0");
         assert_eq!(default.port, 8080);
         assert_eq!(default.path, "data/");
+        assert_eq!(default.snapshot_path, "snapshots/");
         assert_eq!(default.writer_memory, 200_000_000);
         assert_eq!(default.log_level, "info");
         assert_eq!(default.json_parsing_threads, 4);
@@ -281,6 +298,7 @@ mod tests {
         assert_eq!(default.merge_policy.level_log_size, None);
         assert_eq!(default.merge_policy.min_layer_size, None);
         assert_eq!(default.merge_policy.min_merge_size, None);
+        assert!(!default.dynamic_mapping);
         assert_eq!(default.experimental, false);
         assert_eq!(default.experimental_features.master, false);
     }
@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tantivy::directory::{Directory, META_LOCK};
+
+use toshi_types::error::Error;
+use toshi_types::server::{SnapshotIndex, SnapshotInfo, SnapshotManifest};
+
+use crate::handle::{IndexHandle, LocalIndex};
+use crate::utils::write_atomically;
+use crate::Result;
+
+/// Segment files shared by every snapshot in a repository. tantivy never changes a segment file once
+/// it's written and names them uniquely, so a file already here can be reused as is.
+const FILES_DIR: &str = "files";
+const SNAPSHOTS_DIR: &str = "snapshots";
+/// tantivy's list of the files it created in an index directory, only those are garbage collected
+/// once merges no longer need them.
+const MANAGED_FILE: &str = ".managed.json";
+const META_FILE: &str = "meta.json";
+
+/// A directory holding snapshots of indexes, taken while the server is running.
+pub struct Repository {
+    path: PathBuf,
+}
+
+impl Repository {
+    /// The repository `name` under `root`, it's created by its first snapshot.
+    pub fn open(root: &Path, name: &str) -> Result<Self> {
+        check_name("repository", name)?;
+        Ok(Self { path: root.join(name) })
+    }
+
+    pub fn manifest(&self, name: &str) -> Result<SnapshotManifest> {
+        check_name("snapshot", name)?;
+        let path = self.manifest_path(name);
+        if !path.exists() {
+            return Err(Error::UnknownSnapshot(name.into()));
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Commits each index and adds the files of its committed segments to the repository, linking
+    /// or copying only the ones no earlier snapshot added, then records the snapshot's manifest.
+    /// `base_path` is the directory the indexes live in.
+    pub fn snapshot(&self, name: &str, base_path: &Path, indexes: &[LocalIndex]) -> Result<SnapshotInfo> {
+        check_name("snapshot", name)?;
+        let manifest_path = self.manifest_path(name);
+        if manifest_path.exists() {
+            return Err(Error::QueryError(format!("snapshot '{}' already exists", name)));
+        }
+        let files_dir = self.path.join(FILES_DIR);
+        fs::create_dir_all(&files_dir)?;
+        fs::create_dir_all(self.path.join(SNAPSHOTS_DIR))?;
+
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut manifest = SnapshotManifest {
+            name: name.into(),
+            created,
+            indexes: BTreeMap::new(),
+        };
+        let mut info = SnapshotInfo {
+            name: name.into(),
+            indexes: Vec::new(),
+            files_copied: 0,
+            files_reused: 0,
+        };
+        for index in indexes {
+            index.commit()?;
+            let index_path = base_path.join(index.get_name());
+            // tantivy doesn't garbage collect while the meta lock is held, so segments merged away in
+            // the meantime stay on disk until they're copied.
+            let _lock = index
+                .get_index()
+                .directory()
+                .acquire_lock(&META_LOCK)
+                .map_err(|e| Error::IOError(format!("{:?}", e)))?;
+            let meta = index.get_index().load_metas()?;
+            let mut files = BTreeSet::new();
+            for file in meta.segments.iter().flat_map(|segment| segment.list_files()) {
+                let source = index_path.join(&file);
+                // Not every segment has every component, such as a delete file.
+                if !source.exists() {
+                    continue;
+                }
+                let file = file.to_string_lossy().to_string();
+                let dest = files_dir.join(&file);
+                if dest.exists() {
+                    info.files_reused += 1;
+                } else {
+                    link_or_copy(&source, &dest)?;
+                    info.files_copied += 1;
+                }
+                files.insert(file);
+            }
+            let snapshot = SnapshotIndex {
+                settings: index.index_settings().clone(),
+                meta: serde_json::to_string(&meta)?,
+                files,
+            };
+            manifest.indexes.insert(index.get_name(), snapshot);
+            info.indexes.push(index.get_name());
+        }
+        write_atomically(&manifest_path, &serde_json::to_vec(&manifest)?)?;
+        Ok(info)
+    }
+
+    /// Links or copies the files of a snapshotted index into a new index directory at `dest`.
+    pub fn restore_into(&self, index: &SnapshotIndex, dest: &Path) -> Result<()> {
+        fs::create_dir(dest)?;
+        for file in &index.files {
+            link_or_copy(&self.path.join(FILES_DIR).join(file), &dest.join(file))?;
+        }
+        fs::write(dest.join(MANAGED_FILE), serde_json::to_vec(&index.files)?)?;
+        fs::write(dest.join(META_FILE), &index.meta)?;
+        Ok(())
+    }
+
+    fn manifest_path(&self, name: &str) -> PathBuf {
+        self.path.join(SNAPSHOTS_DIR).join(format!("{}.json", name))
+    }
+}
+
+/// Hard links where the filesystem allows it, since segment files are never written to again.
+fn link_or_copy(source: &Path, dest: &Path) -> Result<()> {
+    if fs::hard_link(source, dest).is_err() {
+        fs::copy(source, dest)?;
+    }
+    Ok(())
+}
+
+/// Repository and snapshot names become paths, so they're limited to letters, digits, `-`, `_` and
+/// `.`, and can't start with a `.`.
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if name.is_empty() || name.starts_with('.') || !valid {
+        return Err(Error::QueryError(format!("'{}' is not a valid {} name", name, kind)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("snapshot", "nightly-2026.10.18").is_ok());
+        assert!(check_name("snapshot", "").is_err());
+        assert!(check_name("snapshot", "..").is_err());
+        assert!(check_name("snapshot", "a/b").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toshi_types::error::Error;
+use toshi_types::server::IndexTemplate;
+
+use crate::utils::{glob_match, write_atomically};
+use crate::Result;
+
+/// Name of the file templates are kept in under the catalog's base path.
+const TEMPLATES_FILE: &str = ".templates.json";
+
+/// Named index templates, consulted when a write creates an index that doesn't exist yet.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    /// Where the templates are persisted, templates of a catalog without a base path only live in memory.
+    path: Option<PathBuf>,
+    templates: BTreeMap<String, IndexTemplate>,
+}
+
+impl Templates {
+    /// Loads the templates persisted under `base_path`, if there are any.
+    pub fn load(base_path: &Path) -> Result<Self> {
+        let path = base_path.join(TEMPLATES_FILE);
+        let templates = if path.exists() {
+            serde_json::from_slice(&fs::read(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path: Some(path),
+            templates,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&IndexTemplate> {
+        self.templates.get(name)
+    }
+
+    pub fn all(&self) -> &BTreeMap<String, IndexTemplate> {
+        &self.templates
+    }
+
+    /// Adds or replaces a template. A primary key defined by the template's schema has to be a valid
+    /// key field, one that isn't is inferred when an index is created and checked then.
+    pub fn put(&mut self, name: &str, template: IndexTemplate) -> Result<()> {
+        if template.patterns.is_empty() {
+            return Err(Error::QueryError(format!("template '{}' needs at least one pattern", name)));
+        }
+        if let (Some(schema), Some(key)) = (&template.schema, &template.settings.primary_key) {
+            if schema.0.get_field(key).is_some() {
+                template.settings.validate(&schema.0)?;
+            }
+        }
+        let mut templates = self.templates.clone();
+        templates.insert(name.into(), template);
+        self.save(&templates)?;
+        self.templates = templates;
+        Ok(())
+    }
+
+    /// Removes a template, returning whether it existed.
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if !self.templates.contains_key(name) {
+            return Ok(false);
+        }
+        let mut templates = self.templates.clone();
+        templates.remove(name);
+        self.save(&templates)?;
+        self.templates = templates;
+        Ok(true)
+    }
+
+    /// The template to create `index` with, the highest priority one with a pattern matching its
+    /// name. Ties go to the template whose name sorts first.
+    pub fn matching(&self, index: &str) -> Option<&IndexTemplate> {
+        self.templates
+            .values()
+            .filter(|t| t.patterns.iter().any(|p| glob_match(p, index)))
+            .fold(None, |best: Option<&IndexTemplate>, t| match best {
+                Some(b) if b.priority >= t.priority => Some(b),
+                _ => Some(t),
+            })
+    }
+
+    fn save(&self, templates: &BTreeMap<String, IndexTemplate>) -> Result<()> {
+        if let Some(path) = &self.path {
+            write_atomically(path, &serde_json::to_vec(templates)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn template(body: &str) -> IndexTemplate {
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn test_template_matching() {
+        let mut templates = Templates::default();
+        templates.put("logs", template(r#"{ "patterns": ["logs-*"] }"#)).unwrap();
+        let app = r#"{ "patterns": ["logs-app-*"], "priority": 10, "primary_key": "id" }"#;
+        templates.put("app", template(app)).unwrap();
+
+        let key = |templates: &Templates, index: &str| templates.matching(index).map(|t| t.settings.primary_key.clone());
+        assert_eq!(key(&templates, "logs-app-2026"), Some(Some("id".into())));
+        assert_eq!(key(&templates, "logs-db-2026"), Some(None));
+        assert_eq!(key(&templates, "metrics"), None);
+
+        assert!(templates.remove("app").unwrap());
+        assert!(!templates.remove("app").unwrap());
+        assert_eq!(key(&templates, "logs-app-2026"), Some(None));
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        let mut templates = Templates::default();
+        assert!(templates.put("none", template(r#"{ "patterns": [] }"#)).is_err());
+        let schema = r#"[{ "name": "msg", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } }]"#;
+        let bad_key = format!(r#"{{ "patterns": ["*"], "schema": {}, "primary_key": "msg" }}"#, schema);
+        assert!(templates.put("bad", template(&bad_key)).is_err());
+        assert!(templates.all().is_empty());
+    }
+}
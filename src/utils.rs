@@ -1,3 +1,6 @@
+use std::fs;
+use std::path::Path;
+
 use futures::future;
 use http::header::CONTENT_TYPE;
 use http::{Response, StatusCode};
@@ -38,6 +41,42 @@ pub fn parse_path(path: &str) -> Vec<&str> {
     path.trim_matches('/').split('/').filter(|s| !s.is_empty()).collect()
 }
 
+/// Writes to a temporary file first and renames it into place, so a crash never leaves `path` half written.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> crate::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Matches a name against a pattern where `*` matches any run of characters and `?` any one character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -60,4 +99,15 @@ mod tests {
         assert_eq!(parsed_two[0], "path");
         assert_eq!(parsed_two[1], "two");
     }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("logs-2026-*", "logs-2026-01"));
+        assert!(glob_match("logs-*-0?", "logs-2026-01"));
+        assert!(glob_match("*", "archive"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("logs-2026-*", "logs-2025-12"));
+        assert!(!glob_match("archive", "archives"));
+        assert!(!glob_match("a?", "a"));
+    }
 }
@@ -85,6 +85,14 @@ impl ToshiClient {
         self.client.put(uri, body).map_err(Into::into)
     }
 
+    pub fn index_schema<I>(&self, index: I) -> Result<SchemaBody>
+    where
+        I: ToString,
+    {
+        let uri = self.uri(format!("{}/_schema", index.to_string()));
+        self.client.get(uri)?.json().map_err(Into::into)
+    }
+
     pub fn index_summary<I>(&self, index: I, include_sizes: bool) -> Result<Response<Body>>
     where
         I: ToString,
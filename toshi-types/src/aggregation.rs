@@ -0,0 +1,385 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::query::{Aggregation, SortValue};
+
+/// The result of an [`Aggregation`]. Results from each segment and each shard are combined with
+/// [`AggregationResult::merge`], and [`AggregationResult::finish`] trims them to what was asked for
+/// once everything has been merged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregationResult {
+    Terms(TermsResult),
+    Metrics(MetricsResult),
+    Histogram(BucketsResult),
+    DateHistogram(BucketsResult),
+    Range(BucketsResult),
+}
+
+impl AggregationResult {
+    pub fn merge(self, other: AggregationResult) -> AggregationResult {
+        match (self, other) {
+            (AggregationResult::Terms(a), AggregationResult::Terms(b)) => AggregationResult::Terms(a.merge(b)),
+            (AggregationResult::Metrics(a), AggregationResult::Metrics(b)) => AggregationResult::Metrics(a.merge(b)),
+            (AggregationResult::Histogram(a), AggregationResult::Histogram(b)) => AggregationResult::Histogram(a.merge_by_key(b)),
+            (AggregationResult::DateHistogram(a), AggregationResult::DateHistogram(b)) => {
+                AggregationResult::DateHistogram(a.merge_by_key(b))
+            }
+            (AggregationResult::Range(a), AggregationResult::Range(b)) => AggregationResult::Range(a.merge_in_order(b)),
+            (a, _) => a,
+        }
+    }
+
+    pub fn finish(&mut self, agg: &Aggregation) {
+        match self {
+            AggregationResult::Terms(terms) => {
+                if let Aggregation::Terms { size, .. } = agg {
+                    terms.truncate(*size);
+                }
+            }
+            AggregationResult::Metrics(metrics) => metrics.registers.clear(),
+            AggregationResult::Histogram(buckets) | AggregationResult::DateHistogram(buckets) | AggregationResult::Range(buckets) => {
+                if let Some(aggs) = agg.sub_aggs() {
+                    for bucket in &mut buckets.buckets {
+                        finish_aggregations(&mut bucket.aggregations, aggs);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Merges the aggregations of another segment or shard into `into`, matching them up by name.
+pub fn merge_aggregations(into: &mut BTreeMap<String, AggregationResult>, other: BTreeMap<String, AggregationResult>) {
+    for (name, result) in other {
+        let merged = match into.remove(&name) {
+            Some(existing) => existing.merge(result),
+            None => result,
+        };
+        into.insert(name, merged);
+    }
+}
+
+pub fn finish_aggregations(results: &mut BTreeMap<String, AggregationResult>, aggs: &BTreeMap<String, Aggregation>) {
+    for (name, result) in results.iter_mut() {
+        if let Some(agg) = aggs.get(name) {
+            result.finish(agg);
+        }
+    }
+}
+
+/// The value a terms bucket counts, either a number from a fast field or a term from a text field.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum BucketKey {
+    Number(SortValue),
+    Text(String),
+}
+
+impl BucketKey {
+    /// Numbers are compared numerically and come before text.
+    pub fn compare(&self, other: &BucketKey) -> Ordering {
+        match (self, other) {
+            (BucketKey::Number(a), BucketKey::Number(b)) => a.compare(b),
+            (BucketKey::Text(a), BucketKey::Text(b)) => a.cmp(b),
+            (BucketKey::Number(_), BucketKey::Text(_)) => Ordering::Less,
+            (BucketKey::Text(_), BucketKey::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TermsBucket {
+    pub key: BucketKey,
+    pub doc_count: u64,
+}
+
+impl TermsBucket {
+    pub fn new(key: BucketKey, doc_count: u64) -> Self {
+        Self { key, doc_count }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TermsResult {
+    pub buckets: Vec<TermsBucket>,
+    /// Documents holding a value that didn't make it into the returned buckets.
+    pub sum_other_doc_count: u64,
+}
+
+impl TermsResult {
+    pub fn new(buckets: Vec<TermsBucket>) -> Self {
+        Self {
+            buckets,
+            sum_other_doc_count: 0,
+        }
+    }
+
+    /// Values from other shards may have come back through JSON as a different numeric type, so
+    /// buckets are matched up by comparing their keys numerically.
+    pub fn merge(mut self, other: TermsResult) -> TermsResult {
+        self.buckets.extend(other.buckets);
+        self.buckets.sort_by(|a, b| a.key.compare(&b.key));
+        let mut buckets: Vec<TermsBucket> = Vec::with_capacity(self.buckets.len());
+        for bucket in self.buckets {
+            match buckets.last_mut() {
+                Some(last) if last.key == bucket.key => last.doc_count += bucket.doc_count,
+                _ => buckets.push(bucket),
+            }
+        }
+        Self {
+            buckets,
+            sum_other_doc_count: self.sum_other_doc_count + other.sum_other_doc_count,
+        }
+    }
+
+    /// Keeps the `size` most common values, ties going to the smaller value.
+    pub fn truncate(&mut self, size: usize) {
+        self.buckets
+            .sort_by(|a, b| b.doc_count.cmp(&a.doc_count).then_with(|| a.key.compare(&b.key)));
+        if self.buckets.len() > size {
+            let other: u64 = self.buckets.drain(size..).map(|b| b.doc_count).sum();
+            self.sum_other_doc_count += other;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub key: BucketKey,
+    /// Date histogram keys formatted as RFC 3339.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_as_string: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<f64>,
+    pub doc_count: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aggregations: BTreeMap<String, AggregationResult>,
+}
+
+impl Bucket {
+    pub fn new(key: BucketKey, doc_count: u64) -> Self {
+        Self {
+            key,
+            key_as_string: None,
+            from: None,
+            to: None,
+            doc_count,
+            aggregations: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_key_as_string(mut self, key: String) -> Self {
+        self.key_as_string = Some(key);
+        self
+    }
+
+    pub fn with_bounds(mut self, from: Option<f64>, to: Option<f64>) -> Self {
+        self.from = from;
+        self.to = to;
+        self
+    }
+
+    pub fn with_aggregations(mut self, aggregations: BTreeMap<String, AggregationResult>) -> Self {
+        self.aggregations = aggregations;
+        self
+    }
+
+    fn merge(&mut self, other: Bucket) {
+        self.doc_count += other.doc_count;
+        merge_aggregations(&mut self.aggregations, other.aggregations);
+    }
+}
+
+/// The buckets of a histogram, date histogram or range aggregation. Histogram buckets are ordered by
+/// key and only buckets holding at least one document are returned, range buckets are in the order
+/// their ranges were given.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BucketsResult {
+    pub buckets: Vec<Bucket>,
+}
+
+impl BucketsResult {
+    pub fn new(buckets: Vec<Bucket>) -> Self {
+        Self { buckets }
+    }
+
+    pub fn merge_by_key(mut self, other: BucketsResult) -> BucketsResult {
+        self.buckets.extend(other.buckets);
+        self.buckets.sort_by(|a, b| a.key.compare(&b.key));
+        let mut buckets: Vec<Bucket> = Vec::with_capacity(self.buckets.len());
+        for bucket in self.buckets {
+            match buckets.last_mut() {
+                Some(last) if last.key == bucket.key => last.merge(bucket),
+                _ => buckets.push(bucket),
+            }
+        }
+        Self { buckets }
+    }
+
+    /// Every shard returns a bucket for each range in the order they were given, so buckets are
+    /// matched up by position since keys given in the request don't have to be unique.
+    pub fn merge_in_order(self, other: BucketsResult) -> BucketsResult {
+        if self.buckets.is_empty() {
+            return other;
+        }
+        if other.buckets.is_empty() {
+            return self;
+        }
+        let buckets = self
+            .buckets
+            .into_iter()
+            .zip(other.buckets)
+            .map(|(mut a, b)| {
+                a.merge(b);
+                a
+            })
+            .collect();
+        Self { buckets }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MetricsResult {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub sum: f64,
+    pub avg: Option<f64>,
+    pub cardinality: u64,
+    /// HyperLogLog registers backing `cardinality`, only sent between nodes so shards can be merged.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub registers: Vec<u8>,
+}
+
+impl Default for MetricsResult {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            min: None,
+            max: None,
+            sum: 0.0,
+            avg: None,
+            cardinality: 0,
+            registers: Vec::new(),
+        }
+    }
+}
+
+/// The number of bits of each hash used to pick a register, 2^12 registers gives a standard error
+/// of about 1.6%.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+impl MetricsResult {
+    /// Adds a value, `raw` is the value's fast field encoding and is only used to estimate cardinality.
+    pub fn insert(&mut self, value: f64, raw: u64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = Some(self.min.map_or(value, |m| m.min(value)));
+        self.max = Some(self.max.map_or(value, |m| m.max(value)));
+        if self.registers.is_empty() {
+            self.registers = vec![0; REGISTERS];
+        }
+        let hash = mix(raw);
+        let index = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+        }
+    }
+
+    pub fn merge(mut self, other: MetricsResult) -> MetricsResult {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = match (self.min, other.min) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        if self.registers.is_empty() {
+            self.registers = other.registers;
+        } else if !other.registers.is_empty() {
+            for (a, b) in self.registers.iter_mut().zip(other.registers) {
+                *a = (*a).max(b);
+            }
+        }
+        self.finalize()
+    }
+
+    /// Fills in the values derived from the running totals.
+    pub fn finalize(mut self) -> Self {
+        self.avg = if self.count > 0 { Some(self.sum / self.count as f64) } else { None };
+        self.cardinality = self.estimate();
+        self
+    }
+
+    fn estimate(&self) -> u64 {
+        if self.registers.is_empty() {
+            return 0;
+        }
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-i32::from(*r))).sum();
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        let estimate = alpha * m * m / sum;
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// A fixed hash so every node puts the same value in the same register.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_terms() {
+        let a = TermsResult::new(vec![
+            TermsBucket::new(BucketKey::Number(SortValue::I64(5)), 2),
+            TermsBucket::new(BucketKey::Number(SortValue::I64(-1)), 4),
+            TermsBucket::new(BucketKey::Text("a".into()), 1),
+        ]);
+        let b = TermsResult::new(vec![TermsBucket::new(BucketKey::Number(SortValue::U64(5)), 3)]);
+        let mut merged = AggregationResult::Terms(a).merge(AggregationResult::Terms(b));
+        merged.finish(&Aggregation::terms("f", 1));
+
+        let expected = TermsResult {
+            buckets: vec![TermsBucket::new(BucketKey::Number(SortValue::I64(5)), 5)],
+            sum_other_doc_count: 5,
+        };
+        assert_eq!(merged, AggregationResult::Terms(expected));
+    }
+
+    #[test]
+    fn test_merge_metrics() {
+        let mut a = MetricsResult::default();
+        let mut b = MetricsResult::default();
+        for v in 0..1000u64 {
+            a.insert(v as f64, v);
+            b.insert((v + 500) as f64, v + 500);
+        }
+        let merged = a.finalize().merge(b.finalize());
+        assert_eq!(merged.count, 2000);
+        assert_eq!(merged.min, Some(0.0));
+        assert_eq!(merged.max, Some(1499.0));
+        assert_eq!(merged.avg, Some(749.5));
+        assert!((merged.cardinality as f64 - 1500.0).abs() < 1500.0 * 0.05);
+    }
+}
@@ -1,27 +1,153 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::iter::Sum;
 use std::ops::Add;
 
 use serde::{Deserialize, Serialize};
 
-use crate::query::KeyValue;
+use crate::aggregation::{self, AggregationResult};
+use crate::query::{Aggregation, Cursor, FacetQuery, KeyValue, Order, SortValue};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ScoredDoc<D: Clone> {
     pub score: Option<f32>,
+    /// The index the doc was found in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sort: Vec<SortValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Cursor>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub highlights: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<serde_json::Value>,
     pub doc: D,
 }
 
 impl<D: Clone> ScoredDoc<D> {
     pub fn new(score: Option<f32>, doc: D) -> Self {
-        Self { score, doc }
+        Self {
+            score,
+            index: None,
+            sort: Vec::new(),
+            cursor: None,
+            highlights: BTreeMap::new(),
+            explanation: None,
+            doc,
+        }
+    }
+
+    pub fn with_cursor(cursor: Cursor, doc: D) -> Self {
+        Self {
+            score: Some(cursor.score),
+            index: cursor.index.clone(),
+            sort: cursor.sort.clone(),
+            cursor: Some(cursor),
+            highlights: BTreeMap::new(),
+            explanation: None,
+            doc,
+        }
+    }
+
+    pub fn with_highlights(mut self, highlights: BTreeMap<String, String>) -> Self {
+        self.highlights = highlights;
+        self
+    }
+
+    pub fn with_explanation(mut self, explanation: Option<serde_json::Value>) -> Self {
+        self.explanation = explanation;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FetchedDoc<D: Clone> {
+    pub id: String,
+    pub found: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<D>,
+}
+
+impl<D: Clone> FetchedDoc<D> {
+    pub fn new(id: String, doc: Option<D>) -> Self {
+        Self {
+            id,
+            found: doc.is_some(),
+            doc,
+        }
+    }
+}
+
+/// tantivy's explanation of how a document scored against a query, kept as JSON since
+/// tantivy's `Explanation` can only be serialized.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExplainResult {
+    pub id: String,
+    pub matched: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<serde_json::Value>,
+}
+
+impl ExplainResult {
+    pub fn new(id: String, explanation: Option<serde_json::Value>) -> Self {
+        Self {
+            id,
+            matched: explanation.is_some(),
+            explanation,
+        }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CountResult {
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultiGetResults<D: Clone> {
+    pub docs: Vec<FetchedDoc<D>>,
+}
+
+/// Why one search in a `_msearch` batch failed, `status` is the code it would have got on its own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultiSearchError {
+    pub status: u16,
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MultiSearchItem<D: Clone> {
+    Results(SearchResults<D>),
+    Error(MultiSearchError),
+}
+
+/// One response for each search in a `_msearch` batch, in the order they were given.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultiSearchResults<D: Clone> {
+    pub responses: Vec<MultiSearchItem<D>>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SearchResults<D: Clone> {
+    /// The number of docs returned.
     pub hits: usize,
+    /// The number of docs matching the query across every shard, regardless of paging.
+    #[serde(default)]
+    pub total_hits: usize,
     pub docs: Vec<ScoredDoc<D>>,
+    /// The count of each facet path, those of every field requested one after the other.
+    #[serde(default)]
     pub facets: Vec<KeyValue<String, u64>>,
+    /// The same counts by facet field.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub facet_fields: BTreeMap<String, Vec<KeyValue<String, u64>>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aggregations: BTreeMap<String, AggregationResult>,
+    /// Set when the search opened a scroll, pass it to `_scroll` for the next batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll_id: Option<String>,
 }
 
 impl<D: Clone> Add for SearchResults<D> {
@@ -30,11 +156,33 @@ impl<D: Clone> Add for SearchResults<D> {
     fn add(self, mut rhs: SearchResults<D>) -> Self::Output {
         let mut docs = self.docs;
         let mut facets = self.facets;
+        let mut facet_fields = self.facet_fields;
         let hits = self.hits + rhs.hits;
+        let total_hits = self.total_hits + rhs.total_hits;
         facets.append(&mut rhs.facets);
+        for (field, counts) in std::mem::take(&mut rhs.facet_fields) {
+            let merged = facet_fields.entry(field).or_default();
+            for count in counts {
+                match merged.iter_mut().find(|c| c.field == count.field) {
+                    Some(existing) => existing.value += count.value,
+                    None => merged.push(count),
+                }
+            }
+        }
+        let mut aggregations = self.aggregations;
+        aggregation::merge_aggregations(&mut aggregations, std::mem::take(&mut rhs.aggregations));
+        let scroll_id = self.scroll_id.or_else(|| rhs.scroll_id.take());
         docs.append(&mut rhs.get_docs());
 
-        Self { hits, docs, facets }
+        Self {
+            hits,
+            total_hits,
+            docs,
+            facets,
+            facet_fields,
+            aggregations,
+            scroll_id,
+        }
     }
 }
 
@@ -49,19 +197,83 @@ impl<D: Clone> SearchResults<D> {
         self.docs
     }
 
+    /// Orders the docs by their cursors and keeps only the requested page, used once the results
+    /// from every shard have been merged. Docs without a cursor are placed last.
+    pub fn page(mut self, from: usize, limit: usize, orders: &[Order]) -> Self {
+        self.docs.sort_by(|a, b| match (&a.cursor, &b.cursor) {
+            (Some(a), Some(b)) => a.compare(b, orders),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+        self.docs = self.docs.into_iter().skip(from).take(limit).collect();
+        self.hits = self.docs.len();
+        self
+    }
+
     pub fn new(docs: Vec<ScoredDoc<D>>) -> Self {
-        Self {
-            hits: docs.len(),
-            docs,
-            facets: Vec::new(),
-        }
+        Self::with_facets(docs, Vec::new())
     }
 
     pub fn with_facets(docs: Vec<ScoredDoc<D>>, facets: Vec<KeyValue<String, u64>>) -> Self {
         Self {
             hits: docs.len(),
+            total_hits: docs.len(),
             docs,
             facets,
+            facet_fields: BTreeMap::new(),
+            aggregations: BTreeMap::new(),
+            scroll_id: None,
+        }
+    }
+
+    /// Sets the facet counts by field, along with the flat `facets` list they make up.
+    pub fn with_facet_fields(mut self, facet_fields: BTreeMap<String, Vec<KeyValue<String, u64>>>) -> Self {
+        self.facet_fields = facet_fields;
+        self.flatten_facets();
+        self
+    }
+
+    fn flatten_facets(&mut self) {
+        self.facets = self.facet_fields.values().flatten().cloned().collect();
+    }
+
+    pub fn with_total(mut self, total_hits: usize) -> Self {
+        self.total_hits = total_hits;
+        self
+    }
+
+    pub fn with_aggregations(mut self, aggregations: BTreeMap<String, AggregationResult>) -> Self {
+        self.aggregations = aggregations;
+        self
+    }
+
+    pub fn with_scroll_id(mut self, scroll_id: String) -> Self {
+        self.scroll_id = Some(scroll_id);
+        self
+    }
+
+    /// Trims each aggregation down to what the search asked for, used once the results from every
+    /// shard have been merged.
+    pub fn finish_aggregations(mut self, aggs: &BTreeMap<String, Aggregation>) -> Self {
+        aggregation::finish_aggregations(&mut self.aggregations, aggs);
+        self
+    }
+
+    /// Orders each field's facet counts from largest to smallest and keeps the top `size` if the
+    /// search asked for it, used once the results from every shard have been merged.
+    pub fn finish_facets(mut self, query: &Option<FacetQuery>) -> Self {
+        if self.facet_fields.is_empty() {
+            return self;
+        }
+        let fields = query.as_ref().map(FacetQuery::fields).unwrap_or_default();
+        for (field, counts) in self.facet_fields.iter_mut() {
+            counts.sort_by(|a, b| b.value.cmp(&a.value).then_with(|| a.field.cmp(&b.field)));
+            if let Some((_, _, Some(size))) = fields.iter().find(|(name, _, _)| name == field) {
+                counts.truncate(*size);
+            }
         }
+        self.flatten_facets();
+        self
     }
 }
@@ -30,6 +30,16 @@ pub enum Error {
     UnknownIndex(String),
     #[error("Error in query execution: '{0}'")]
     QueryError(String),
+    #[error("Invalid primary key: {0}")]
+    InvalidPrimaryKey(String),
+    #[error("Document with key '{0}' already exists")]
+    DuplicateKey(String),
+    #[error("Unknown Document: '{0}' does not exist")]
+    UnknownDocument(String),
+    #[error("Unknown Scroll: '{0}' has expired or does not exist")]
+    UnknownScroll(String),
+    #[error("Unknown Snapshot: '{0}' does not exist")]
+    UnknownSnapshot(String),
     #[error("Failed to find known executor")]
     SpawnError,
     #[error("An unknown error occurred")]
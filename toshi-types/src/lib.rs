@@ -1,3 +1,4 @@
+pub mod aggregation;
 pub mod client;
 pub mod error;
 pub mod query;
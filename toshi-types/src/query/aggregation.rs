@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tantivy::chrono::{Datelike, NaiveDate, NaiveDateTime};
+
+/// An aggregation computed over every document matching a search, not just the returned page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// The most common values of a u64, i64 or f64 fast field, or the most common terms of an
+    /// indexed text field, with the number of documents holding each. Keyword fields should be
+    /// indexed with the raw tokenizer so each value is counted whole.
+    Terms {
+        field: String,
+        #[serde(default = "Aggregation::default_size")]
+        size: usize,
+    },
+    /// Count, min, max, sum, avg and approximate cardinality of a u64, i64 or f64 fast field.
+    Metrics { field: String },
+    /// Buckets of a fixed width, keyed by their lower bound.
+    Histogram {
+        field: String,
+        interval: f64,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        aggs: BTreeMap<String, Aggregation>,
+    },
+    /// Buckets of a calendar interval in UTC, keyed by the timestamp in seconds they start at.
+    DateHistogram {
+        field: String,
+        interval: CalendarInterval,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        aggs: BTreeMap<String, Aggregation>,
+    },
+    /// One bucket for each of the given ranges, a value can fall in more than one.
+    Range {
+        field: String,
+        ranges: Vec<AggregationRange>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        aggs: BTreeMap<String, Aggregation>,
+    },
+}
+
+impl Aggregation {
+    pub fn terms<F: ToString>(field: F, size: usize) -> Self {
+        Aggregation::Terms {
+            field: field.to_string(),
+            size,
+        }
+    }
+
+    pub fn metrics<F: ToString>(field: F) -> Self {
+        Aggregation::Metrics { field: field.to_string() }
+    }
+
+    pub fn histogram<F: ToString>(field: F, interval: f64) -> Self {
+        Aggregation::Histogram {
+            field: field.to_string(),
+            interval,
+            aggs: BTreeMap::new(),
+        }
+    }
+
+    pub fn date_histogram<F: ToString>(field: F, interval: CalendarInterval) -> Self {
+        Aggregation::DateHistogram {
+            field: field.to_string(),
+            interval,
+            aggs: BTreeMap::new(),
+        }
+    }
+
+    pub fn range<F: ToString>(field: F, ranges: Vec<AggregationRange>) -> Self {
+        Aggregation::Range {
+            field: field.to_string(),
+            ranges,
+            aggs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an aggregation computed within each bucket, this does nothing for terms or metrics.
+    pub fn with_aggregation<N: ToString>(mut self, name: N, agg: Aggregation) -> Self {
+        if let Some(aggs) = self.sub_aggs_mut() {
+            aggs.insert(name.to_string(), agg);
+        }
+        self
+    }
+
+    pub fn default_size() -> usize {
+        10
+    }
+
+    pub fn field(&self) -> &str {
+        match self {
+            Aggregation::Terms { field, .. }
+            | Aggregation::Metrics { field }
+            | Aggregation::Histogram { field, .. }
+            | Aggregation::DateHistogram { field, .. }
+            | Aggregation::Range { field, .. } => field,
+        }
+    }
+
+    /// The aggregations computed within each bucket of a bucket aggregation.
+    pub fn sub_aggs(&self) -> Option<&BTreeMap<String, Aggregation>> {
+        match self {
+            Aggregation::Histogram { aggs, .. } | Aggregation::DateHistogram { aggs, .. } | Aggregation::Range { aggs, .. } => Some(aggs),
+            _ => None,
+        }
+    }
+
+    fn sub_aggs_mut(&mut self) -> Option<&mut BTreeMap<String, Aggregation>> {
+        match self {
+            Aggregation::Histogram { aggs, .. } | Aggregation::DateHistogram { aggs, .. } | Aggregation::Range { aggs, .. } => Some(aggs),
+            _ => None,
+        }
+    }
+
+    /// How many terms buckets each shard returns. Asking shards for more than the final size
+    /// makes it less likely a value that is common overall is missing from the merged top terms.
+    pub fn shard_size(size: usize) -> usize {
+        size + size / 2 + 10
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarInterval {
+    Minute,
+    Hour,
+    Day,
+    /// Weeks start on Monday.
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl CalendarInterval {
+    /// The start of the interval holding `timestamp`, both in seconds since the epoch.
+    pub fn floor(self, timestamp: i64) -> i64 {
+        let fixed = |width: i64| timestamp - timestamp.rem_euclid(width);
+        let date = NaiveDateTime::from_timestamp(timestamp.div_euclid(86_400) * 86_400, 0).date();
+        let start = |date: NaiveDate| date.and_hms(0, 0, 0).timestamp();
+        match self {
+            CalendarInterval::Minute => fixed(60),
+            CalendarInterval::Hour => fixed(3600),
+            CalendarInterval::Day => fixed(86_400),
+            // The epoch fell on a Thursday.
+            CalendarInterval::Week => (timestamp + 3 * 86_400).div_euclid(7 * 86_400) * 7 * 86_400 - 3 * 86_400,
+            CalendarInterval::Month => start(NaiveDate::from_ymd(date.year(), date.month(), 1)),
+            CalendarInterval::Quarter => start(NaiveDate::from_ymd(date.year(), (date.month() - 1) / 3 * 3 + 1, 1)),
+            CalendarInterval::Year => start(NaiveDate::from_ymd(date.year(), 1, 1)),
+        }
+    }
+}
+
+/// A range bucket holding values from `from` inclusive up to `to` exclusive, a missing bound is unbounded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AggregationRange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<f64>,
+}
+
+impl AggregationRange {
+    pub fn new(from: Option<f64>, to: Option<f64>) -> Self {
+        Self { key: None, from, to }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        let after_from = match self.from {
+            Some(from) => value >= from,
+            None => true,
+        };
+        let before_to = match self.to {
+            Some(to) => value < to,
+            None => true,
+        };
+        after_from && before_to
+    }
+
+    /// The key given in the request, or one made from the bounds such as `*-100`.
+    pub fn key(&self) -> String {
+        let bound = |b: Option<f64>| b.map_or_else(|| "*".to_string(), |b| b.to_string());
+        self.key
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}", bound(self.from), bound(self.to)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar_floor() {
+        // 2019-08-14T13:45:30Z, a Wednesday
+        let ts = 1_565_790_330;
+        assert_eq!(CalendarInterval::Minute.floor(ts), 1_565_790_300);
+        assert_eq!(CalendarInterval::Hour.floor(ts), 1_565_787_600);
+        assert_eq!(CalendarInterval::Day.floor(ts), 1_565_740_800);
+        assert_eq!(CalendarInterval::Week.floor(ts), 1_565_568_000);
+        assert_eq!(CalendarInterval::Month.floor(ts), 1_564_617_600);
+        assert_eq!(CalendarInterval::Quarter.floor(ts), 1_561_939_200);
+        assert_eq!(CalendarInterval::Year.floor(ts), 1_546_300_800);
+        assert_eq!(CalendarInterval::Day.floor(-1), -86_400);
+    }
+
+    #[test]
+    fn test_range_keys() {
+        let range: AggregationRange = serde_json::from_str(r#"{ "to": 100 }"#).unwrap();
+        assert_eq!(range.key(), "*-100");
+        assert!(range.contains(99.5));
+        assert!(!range.contains(100.0));
+        assert_eq!(AggregationRange::new(Some(1.5), None).key(), "1.5-*");
+    }
+}
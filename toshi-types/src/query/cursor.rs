@@ -0,0 +1,108 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::query::{Order, SortValue};
+
+/// The position of a hit in a result set, returned with every hit so the last one can be passed back
+/// as `search_after` to fetch the next page. Hits are ordered by their sort values if the search has
+/// any, then by descending score, and ties are broken by index name and doc address so the order is
+/// the same for every request against the same readers. Doc addresses change when an index commits or
+/// merges segments, so hits tied on every sort value and score can be skipped or repeated by pages
+/// fetched on either side of a commit. Sorting on a unique field such as the primary key, or reading
+/// with a scroll, keeps pages stable.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cursor {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sort: Vec<SortValue>,
+    pub score: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    pub segment: u32,
+    pub doc: u32,
+}
+
+impl Cursor {
+    pub fn new(score: f32, segment: u32, doc: u32) -> Self {
+        Self {
+            sort: Vec::new(),
+            score,
+            index: None,
+            segment,
+            doc,
+        }
+    }
+
+    pub fn with_sort(mut self, sort: Vec<SortValue>) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    pub fn with_index(mut self, index: Option<String>) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Compares two cursors in result order, the best hit being the smallest. `orders` holds the
+    /// direction of each of the search's sort fields.
+    pub fn compare(&self, other: &Cursor, orders: &[Order]) -> Ordering {
+        self.sort
+            .iter()
+            .zip(&other.sort)
+            .zip(orders)
+            .map(|((a, b), order)| order.apply(a.compare(b)))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.score.partial_cmp(&self.score).unwrap_or(Ordering::Equal))
+            .then_with(|| self.index.cmp(&other.index))
+            .then(self.segment.cmp(&other.segment))
+            .then(self.doc.cmp(&other.doc))
+    }
+
+    /// Whether `other` comes strictly after this cursor in result order.
+    pub fn is_before(&self, other: &Cursor, orders: &[Order]) -> bool {
+        self.compare(other, orders) == Ordering::Less
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_order() {
+        let mut cursors = vec![
+            Cursor::new(1.0, 1, 0),
+            Cursor::new(2.0, 1, 5),
+            Cursor::new(1.0, 0, 3),
+            Cursor::new(1.0, 0, 1),
+        ];
+        cursors.sort_by(|a, b| a.compare(b, &[]));
+        assert_eq!(
+            cursors,
+            vec![
+                Cursor::new(2.0, 1, 5),
+                Cursor::new(1.0, 0, 1),
+                Cursor::new(1.0, 0, 3),
+                Cursor::new(1.0, 1, 0)
+            ]
+        );
+        assert!(cursors[1].is_before(&cursors[2], &[]));
+        assert!(!cursors[2].is_before(&cursors[2], &[]));
+    }
+
+    #[test]
+    fn test_sorted_cursor_order() {
+        let low = Cursor::new(1.0, 0, 0).with_sort(vec![SortValue::I64(-5)]);
+        let high = Cursor::new(2.0, 0, 1).with_sort(vec![SortValue::I64(5)]);
+        assert!(low.is_before(&high, &[Order::Asc]));
+        assert!(high.is_before(&low, &[Order::Desc]));
+
+        let tied = Cursor::new(3.0, 0, 2).with_sort(vec![SortValue::U64(5)]);
+        assert!(tied.is_before(&high, &[Order::Asc]));
+
+        let a = Cursor::new(1.0, 1, 5).with_index(Some("a".into()));
+        let b = Cursor::new(1.0, 0, 0).with_index(Some("b".into()));
+        assert!(a.is_before(&b, &[]));
+    }
+}
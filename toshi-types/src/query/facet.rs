@@ -1,24 +1,114 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 use crate::query::KeyValue;
 
+/// Counts matching documents under the children of one or more paths. The original shape, a single
+/// facet field mapped to a list of paths, is kept as is. Any other query maps each field to either a
+/// list of paths or `{ "paths": [...], "size": k }` to only keep the `k` largest counts for that field.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FacetQuery(FacetFields);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct FacetQuery(KeyValue<String, Vec<String>>);
+#[serde(untagged)]
+enum FacetFields {
+    Single(KeyValue<String, Vec<String>>),
+    Fields(BTreeMap<String, FacetField>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FacetField {
+    Paths(Vec<String>),
+    Options {
+        paths: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<usize>,
+    },
+}
+
+impl FacetField {
+    pub fn paths(&self) -> &[String] {
+        match self {
+            FacetField::Paths(paths) | FacetField::Options { paths, .. } => paths,
+        }
+    }
+
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            FacetField::Paths(_) => None,
+            FacetField::Options { size, .. } => *size,
+        }
+    }
+}
 
 impl FacetQuery {
     pub fn new(facets: KeyValue<String, Vec<String>>) -> Self {
-        Self(facets)
+        Self(FacetFields::Single(facets))
     }
 
     pub fn with_terms(field: String, terms: Vec<String>) -> Self {
-        Self(KeyValue::new(field, terms))
+        Self(FacetFields::Single(KeyValue::new(field, terms)))
     }
 
+    /// Adds another field, or replaces the paths of one the query already has.
+    pub fn with_field(self, field: String, paths: Vec<String>, size: Option<usize>) -> Self {
+        let mut fields = match self.0 {
+            FacetFields::Single(kv) => vec![(kv.field, FacetField::Paths(kv.value))].into_iter().collect(),
+            FacetFields::Fields(fields) => fields,
+        };
+        let facet = match size {
+            Some(_) => FacetField::Options { paths, size },
+            None => FacetField::Paths(paths),
+        };
+        fields.insert(field, facet);
+        Self(FacetFields::Fields(fields))
+    }
+
+    /// Each field with its paths and the number of counts to keep, if limited.
+    pub fn fields(&self) -> Vec<(&str, &[String], Option<usize>)> {
+        match &self.0 {
+            FacetFields::Single(kv) => vec![(kv.field.as_str(), kv.value.as_slice(), None)],
+            FacetFields::Fields(fields) => fields.iter().map(|(name, f)| (name.as_str(), f.paths(), f.size())).collect(),
+        }
+    }
+
+    /// The paths of a single field query, or of the first field of one with several.
     pub fn get_facets_values(&self) -> &[String] {
-        &self.0.value
+        self.fields().first().map_or(&[], |(_, paths, _)| paths)
     }
 
+    /// The field of a single field query, or the first field of one with several.
     pub fn get_facets_fields(&self) -> &str {
-        &self.0.field
+        self.fields().first().map_or("", |(field, _, _)| field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_facet_fields() {
+        let body = r#"{ "tags": ["/a", "/b"], "category": { "paths": ["/"], "size": 3 } }"#;
+        let query: FacetQuery = serde_json::from_str(body).unwrap();
+        let expected =
+            FacetQuery::with_terms("tags".into(), vec!["/a".into(), "/b".into()]).with_field("category".into(), vec!["/".into()], Some(3));
+        assert_eq!(query.fields(), expected.fields());
+        let paths = ["/a".to_string(), "/b".to_string()];
+        assert_eq!(
+            query.fields(),
+            vec![("category", &["/".to_string()][..], Some(3)), ("tags", &paths[..], None)]
+        );
+    }
+
+    #[test]
+    fn test_single_field() {
+        let body = r#"{ "tags": ["/a", "/b"] }"#;
+        let query: FacetQuery = serde_json::from_str(body).unwrap();
+        assert_eq!(query.get_facets_fields(), "tags");
+        assert_eq!(query.get_facets_values(), &["/a".to_string(), "/b".to_string()][..]);
+        assert_eq!(serde_json::to_string(&query).unwrap(), r#"{"tags":["/a","/b"]}"#);
     }
 }
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tantivy::Snippet;
+
+/// Asks for a highlighted fragment of each named text field to be returned with every hit. Fragments
+/// are returned as plain text with only the tags added, so callers rendering them as HTML should
+/// pick tags and escaping to suit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Highlight {
+    pub fields: BTreeMap<String, HighlightField>,
+    #[serde(default = "Highlight::default_pre_tag")]
+    pub pre_tag: String,
+    #[serde(default = "Highlight::default_post_tag")]
+    pub post_tag: String,
+    #[serde(default = "Highlight::default_fragment_size")]
+    pub fragment_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct HighlightField {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment_size: Option<usize>,
+}
+
+impl Highlight {
+    pub fn new<F: ToString>(fields: Vec<F>) -> Self {
+        Self {
+            fields: fields.into_iter().map(|f| (f.to_string(), HighlightField::default())).collect(),
+            pre_tag: Self::default_pre_tag(),
+            post_tag: Self::default_post_tag(),
+            fragment_size: Self::default_fragment_size(),
+        }
+    }
+
+    pub fn default_pre_tag() -> String {
+        "<b>".into()
+    }
+
+    pub fn default_post_tag() -> String {
+        "</b>".into()
+    }
+
+    pub fn default_fragment_size() -> usize {
+        150
+    }
+
+    pub fn fragment_size(&self, field: &str) -> usize {
+        self.fields.get(field).and_then(|f| f.fragment_size).unwrap_or(self.fragment_size)
+    }
+
+    /// Wraps each highlighted section of the snippet in this highlight's tags.
+    pub fn render(&self, snippet: &Snippet) -> String {
+        let text = snippet.fragments();
+        let mut rendered = String::with_capacity(text.len());
+        let mut start = 0;
+        for section in snippet.highlighted() {
+            let (from, to) = section.bounds();
+            rendered.push_str(&text[start..from]);
+            rendered.push_str(&self.pre_tag);
+            rendered.push_str(&text[from..to]);
+            rendered.push_str(&self.post_tag);
+            start = to;
+        }
+        rendered.push_str(&text[start..]);
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_defaults() {
+        let body = r#"{ "fields": { "body": {}, "title": { "fragment_size": 20 } }, "pre_tag": "<em>" }"#;
+        let highlight: Highlight = serde_json::from_str(body).unwrap();
+        assert_eq!(highlight.pre_tag, "<em>");
+        assert_eq!(highlight.post_tag, "</b>");
+        assert_eq!(highlight.fragment_size("body"), 150);
+        assert_eq!(highlight.fragment_size("title"), 20);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt;
 use std::marker::PhantomData;
 
@@ -11,21 +12,29 @@ use tantivy::Term;
 
 use crate::error::Error;
 pub use crate::query::{
+    aggregation::{Aggregation, AggregationRange, CalendarInterval},
     boolean::{BoolQuery, BoolQueryBuilder},
-    facet::FacetQuery,
+    cursor::Cursor,
+    facet::{FacetField, FacetQuery},
     fuzzy::{FuzzyQuery, FuzzyQueryBuilder, FuzzyTerm},
+    highlight::{Highlight, HighlightField},
     phrase::{PhraseQuery, TermPair},
     range::{RangeQuery, RangeQueryBuilder, Ranges},
     regex::RegexQuery,
+    sort::{Order, SortField, SortValue},
     term::ExactTerm,
 };
 
+mod aggregation;
 mod boolean;
+mod cursor;
 mod facet;
 mod fuzzy;
+mod highlight;
 mod phrase;
 mod range;
 mod regex;
+mod sort;
 mod term;
 
 pub trait CreateQuery {
@@ -45,17 +54,98 @@ pub enum Query {
     All,
 }
 
+/// The deepest `from + limit` a search can page to, every shard keeps that many hits in memory while
+/// it searches. Deeper pages need `search_after` or a scroll.
+pub const MAX_RESULT_WINDOW: usize = 10_000;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Search {
     pub query: Option<Query>,
     pub facets: Option<FacetQuery>,
     #[serde(default = "Search::default_limit")]
     pub limit: usize,
+    #[serde(default)]
+    pub from: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_after: Option<Cursor>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sort: Vec<SortField>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+    #[serde(default, rename = "_source", skip_serializing_if = "SourceFilter::is_empty")]
+    pub source: SourceFilter,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<Highlight>,
+    #[serde(default)]
+    pub explain: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aggs: BTreeMap<String, Aggregation>,
+}
+
+/// Selects which stored fields are returned for each hit, an empty `includes` means every stored field.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SourceFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>,
+}
+
+impl SourceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.includes.is_empty() && self.excludes.is_empty()
+    }
 }
 
 impl Search {
     pub fn new(query: Option<Query>, facets: Option<FacetQuery>, limit: usize) -> Self {
-        Search { query, facets, limit }
+        Search {
+            query,
+            facets,
+            limit,
+            from: 0,
+            search_after: None,
+            sort: Vec::new(),
+            fields: Vec::new(),
+            source: SourceFilter::default(),
+            highlight: None,
+            explain: false,
+            aggs: BTreeMap::new(),
+        }
+    }
+
+    /// The stored fields to return for each hit, `fields` is shorthand for `_source.includes`.
+    pub fn included_fields(&self) -> impl Iterator<Item = &String> {
+        self.fields.iter().chain(self.source.includes.iter())
+    }
+
+    pub fn sort_orders(&self) -> Vec<Order> {
+        self.sort.iter().map(|s| s.order).collect()
+    }
+
+    /// How many hits have to be collected to fill the requested page, which can't be more than
+    /// `MAX_RESULT_WINDOW`.
+    pub fn window(&self) -> crate::Result<usize> {
+        self.from
+            .checked_add(self.limit)
+            .filter(|window| *window <= MAX_RESULT_WINDOW)
+            .ok_or_else(|| {
+                Error::QueryError(format!(
+                    "from + limit can't be more than {}, page further with search_after or a scroll",
+                    MAX_RESULT_WINDOW
+                ))
+            })
+    }
+
+    /// The request each shard of an index is sent, every shard has to return enough hits to fill
+    /// the requested page on its own since the page is only cut once all shard results are merged.
+    /// The page is expected to have been checked with `window` already.
+    pub fn for_shards(&self) -> Self {
+        Search {
+            limit: self.from.saturating_add(self.limit),
+            from: 0,
+            ..self.clone()
+        }
     }
 
     pub fn builder() -> SearchBuilder {
@@ -75,11 +165,7 @@ impl Search {
     }
 
     pub fn all_docs() -> Self {
-        Self {
-            query: Self::all_query(),
-            facets: None,
-            limit: Self::default_limit(),
-        }
+        Self::new(Self::all_query(), None, Self::default_limit())
     }
 }
 
@@ -87,6 +173,13 @@ pub struct SearchBuilder {
     query: Query,
     facets: Option<FacetQuery>,
     limit: usize,
+    from: usize,
+    search_after: Option<Cursor>,
+    sort: Vec<SortField>,
+    source: SourceFilter,
+    highlight: Option<Highlight>,
+    explain: bool,
+    aggs: BTreeMap<String, Aggregation>,
 }
 
 impl Default for SearchBuilder {
@@ -101,6 +194,13 @@ impl SearchBuilder {
             query: Query::All,
             facets: None,
             limit: 100,
+            from: 0,
+            search_after: None,
+            sort: Vec::new(),
+            source: SourceFilter::default(),
+            highlight: None,
+            explain: false,
+            aggs: BTreeMap::new(),
         }
     }
 
@@ -116,8 +216,49 @@ impl SearchBuilder {
         self.limit = limit;
         self
     }
+    pub fn with_from(mut self, from: usize) -> Self {
+        self.from = from;
+        self
+    }
+    pub fn with_search_after(mut self, cursor: Cursor) -> Self {
+        self.search_after = Some(cursor);
+        self
+    }
+    pub fn with_sort(mut self, sort: SortField) -> Self {
+        self.sort.push(sort);
+        self
+    }
+    pub fn with_includes(mut self, fields: Vec<String>) -> Self {
+        self.source.includes = fields;
+        self
+    }
+    pub fn with_excludes(mut self, fields: Vec<String>) -> Self {
+        self.source.excludes = fields;
+        self
+    }
+    pub fn with_highlight(mut self, highlight: Highlight) -> Self {
+        self.highlight = Some(highlight);
+        self
+    }
+    pub fn with_explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+    pub fn with_aggregation<N: ToString>(mut self, name: N, agg: Aggregation) -> Self {
+        self.aggs.insert(name.to_string(), agg);
+        self
+    }
     pub fn build(self) -> Search {
-        Search::new(Some(self.query), self.facets, self.limit)
+        Search {
+            from: self.from,
+            search_after: self.search_after,
+            sort: self.sort,
+            source: self.source,
+            highlight: self.highlight,
+            explain: self.explain,
+            aggs: self.aggs,
+            ..Search::new(Some(self.query), self.facets, self.limit)
+        }
     }
 }
 
@@ -231,4 +372,13 @@ pub mod tests {
         let expected = r#"{"test_field":1}"#;
         assert_eq!(expected, serde_json::to_string(&kv).unwrap());
     }
+
+    #[test]
+    fn test_result_window() {
+        let search = Search::builder().with_from(20).with_limit(10).build();
+        assert_eq!(search.window().unwrap(), 30);
+        assert_eq!(search.for_shards().limit, 30);
+        assert!(Search::builder().with_from(usize::MAX).with_limit(10).build().window().is_err());
+        assert!(Search::builder().with_limit(MAX_RESULT_WINDOW + 1).build().window().is_err());
+    }
 }
@@ -0,0 +1,99 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Asc
+    }
+}
+
+impl Order {
+    pub fn apply(self, ordering: Ordering) -> Ordering {
+        match self {
+            Order::Asc => ordering,
+            Order::Desc => ordering.reverse(),
+        }
+    }
+}
+
+/// A fast field to order results by, sort fields are applied in the order given and the
+/// score is always used to break ties between them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SortField {
+    pub field: String,
+    #[serde(default)]
+    pub order: Order,
+}
+
+impl SortField {
+    pub fn new<F: ToString>(field: F, order: Order) -> Self {
+        Self {
+            field: field.to_string(),
+            order,
+        }
+    }
+}
+
+/// The value of a sort field for a hit. Values go through JSON on their way to and from clients and
+/// other nodes, where an i64 can come back as a u64, so they are always compared numerically.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum SortValue {
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+impl SortValue {
+    pub fn as_f64(self) -> f64 {
+        match self {
+            SortValue::U64(v) => v as f64,
+            SortValue::I64(v) => v as f64,
+            SortValue::F64(v) => v,
+        }
+    }
+
+    pub fn compare(&self, other: &SortValue) -> Ordering {
+        match (*self, *other) {
+            (SortValue::U64(a), SortValue::U64(b)) => a.cmp(&b),
+            (SortValue::I64(a), SortValue::I64(b)) => a.cmp(&b),
+            (SortValue::U64(a), SortValue::I64(b)) => i128::from(a).cmp(&i128::from(b)),
+            (SortValue::I64(a), SortValue::U64(b)) => i128::from(a).cmp(&i128::from(b)),
+            (a, b) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        }
+    }
+}
+
+impl PartialEq for SortValue {
+    fn eq(&self, other: &SortValue) -> bool {
+        self.compare(other) == Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mixed_values() {
+        let value: SortValue = serde_json::from_str("2014").unwrap();
+        assert_eq!(value.compare(&SortValue::I64(2014)), Ordering::Equal);
+        assert_eq!(SortValue::I64(-1).compare(&SortValue::U64(0)), Ordering::Less);
+        assert_eq!(SortValue::F64(1.5).compare(&SortValue::U64(1)), Ordering::Greater);
+        assert_eq!(Order::Desc.apply(Ordering::Less), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_sort_field_default_order() {
+        let sort: SortField = serde_json::from_str(r#"{ "field": "price" }"#).unwrap();
+        assert_eq!(sort, SortField::new("price", Order::Asc));
+    }
+}
@@ -1,14 +1,33 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use serde::{Deserialize, Serialize};
 use std::fmt::Formatter;
-use tantivy::schema::Schema;
+use tantivy::schema::{FieldType, Schema};
+
+use crate::error::Error;
+use crate::query::Query;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DocsAffected {
     pub docs_affected: u64,
 }
 
+/// The outcome of a bulk insert: how many documents were indexed, and why each line or document that
+/// wasn't, such as one whose primary key is already in the index, was skipped.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BulkResult {
+    pub docs_affected: u64,
+    pub skipped: Vec<String>,
+}
+
+/// The result of adding fields to an index's schema. `reindexed` is set when the fields couldn't be
+/// added to the existing segments and every document was copied into a new index instead.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SchemaChange {
+    pub schema_version: u64,
+    pub reindexed: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct IndexOptions {
     #[serde(default)]
@@ -21,6 +40,29 @@ pub struct AddDocument<D> {
     pub document: D,
 }
 
+/// How an update by id treats the supplied document. An upsert replaces the whole document, creating
+/// it if the id doesn't exist yet, while a partial update merges the supplied fields into an existing one.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateMode {
+    Upsert,
+    Partial,
+}
+
+impl Default for UpdateMode {
+    fn default() -> Self {
+        UpdateMode::Partial
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateDocument<D> {
+    pub options: Option<IndexOptions>,
+    #[serde(default)]
+    pub mode: UpdateMode,
+    pub document: D,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SchemaBody(pub Schema);
 
@@ -40,3 +82,211 @@ pub struct DeleteDoc {
     pub options: Option<IndexOptions>,
     pub terms: HashMap<String, String>,
 }
+
+/// Deletes every document matching `query`, the deletes are always committed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeleteByQuery {
+    pub query: Query,
+}
+
+/// Rewrites every document matching `query` with `updates` applied to it, keyed by field name.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateByQuery {
+    pub query: Query,
+    pub updates: BTreeMap<String, FieldUpdate>,
+}
+
+/// A change made to one field of each document an update by query rewrites.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldUpdate {
+    /// Replaces every value of the field, an array sets several values.
+    Set(serde_json::Value),
+    /// Adds to the first value of a u64, i64 or f64 field, a document without one starts from 0.
+    Increment(serde_json::Number),
+}
+
+/// Copies the documents of one index into another, for example to move to a new schema.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Reindex {
+    pub source: ReindexSource,
+    pub dest: ReindexDest,
+    /// New names for source fields, keyed by their name in the source.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rename: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReindexSource {
+    pub index: String,
+    /// Only copy the documents matching this query, every document is copied without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<Query>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReindexDest {
+    pub index: String,
+    /// Creates the destination with this schema if it doesn't exist yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<SchemaBody>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_key: Option<String>,
+}
+
+/// The indexes an alias resolves to. Searching the alias covers all of them, while writes and
+/// lookups by id go to `write_index`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Alias {
+    pub indexes: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_index: Option<String>,
+}
+
+/// Alias changes that are applied together, either every action takes effect or none do.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AliasActions {
+    pub actions: Vec<AliasAction>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AliasAction {
+    /// Points `alias` at `index` as well, creating the alias if needed. An alias with a single index
+    /// writes to it, one with several needs one of them added with `is_write_index`.
+    Add {
+        index: String,
+        alias: String,
+        #[serde(default)]
+        is_write_index: bool,
+    },
+    Remove {
+        index: String,
+        alias: String,
+    },
+}
+
+/// Toshi specific settings for an index that tantivy has no notion of, these are persisted
+/// alongside the index so they survive restarts.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct IndexSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_key: Option<String>,
+    /// Bumped every time fields are added to the index's schema.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub schema_version: u64,
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+impl IndexSettings {
+    pub fn with_primary_key<K: ToString>(key: K) -> Self {
+        Self {
+            primary_key: Some(key.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// A primary key has to be stored so it can be returned, and indexed as a single term so
+    /// documents can be found by it. That rules out text fields that aren't using the raw tokenizer.
+    pub fn validate(&self, schema: &Schema) -> crate::Result<()> {
+        if let Some(key) = &self.primary_key {
+            let field = schema.get_field(key).ok_or_else(|| Error::UnknownIndexField(key.clone()))?;
+            let entry = schema.get_field_entry(field);
+            if !entry.is_indexed() || !entry.is_stored() {
+                return Err(Error::InvalidPrimaryKey(format!("'{}' must be both indexed and stored", key)));
+            }
+            match entry.field_type() {
+                FieldType::U64(_) | FieldType::I64(_) => (),
+                FieldType::Str(opts) => {
+                    let raw = opts.get_indexing_options().map(|i| i.tokenizer() == "raw").unwrap_or(false);
+                    if !raw {
+                        return Err(Error::InvalidPrimaryKey(format!("text field '{}' must use the raw tokenizer", key)));
+                    }
+                }
+                _ => return Err(Error::InvalidPrimaryKey(format!("'{}' must be a text, u64 or i64 field", key))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Defaults for indexes created by their first write, used by new indexes whose names match one of
+/// `patterns`. When several templates match, the one with the highest `priority` wins.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexTemplate {
+    pub patterns: Vec<String>,
+    /// Fields every matching index starts with, any other field is inferred from the first document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<SchemaBody>,
+    #[serde(default, flatten)]
+    pub settings: IndexSettings,
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// Selects the indexes a snapshot is taken of or restored, as a comma separated list of names and
+/// glob patterns. Every index is selected when it's left out.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SnapshotRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indexes: Option<String>,
+}
+
+/// What a snapshot holds, kept in the repository next to the segment files the snapshots share.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SnapshotManifest {
+    pub name: String,
+    /// When the snapshot was taken, in seconds since the epoch.
+    pub created: u64,
+    pub indexes: BTreeMap<String, SnapshotIndex>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SnapshotIndex {
+    #[serde(default)]
+    pub settings: IndexSettings,
+    /// The index's `meta.json` as of the snapshot, kept as text since tantivy expects its keys in the
+    /// order it wrote them.
+    pub meta: String,
+    /// The segment files the index needs, by name in the repository.
+    pub files: BTreeSet<String>,
+}
+
+/// The outcome of taking a snapshot, `files_reused` counts segment files an earlier snapshot had
+/// already put in the repository.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub indexes: Vec<String>,
+    pub files_copied: usize,
+    pub files_reused: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RestoreInfo {
+    pub indexes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MultiGet {
+    pub ids: Vec<String>,
+}
+
+/// Fetches the next batch of a scroll, `scroll` optionally replaces how long the scroll is kept
+/// alive between calls, such as `30s`, `5m` or `1h`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScrollRequest {
+    pub scroll_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll: Option<String>,
+}
+
+/// The header line before each search in a `_msearch` body, `index` takes the same names and
+/// patterns as the search path.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MultiSearchHeader {
+    pub index: String,
+}